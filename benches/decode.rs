@@ -0,0 +1,134 @@
+//! Decoding recorded venue messages, against the owned `Vec<Vec<String>>` parsing it replaced.
+//!
+//! The decoders still allocate twice per message: the levels parse into buffers they keep, and the
+//! operation gets a copy of each sized to fit, since it outlives the message on the book channel.
+//! Handing the buffers out instead would need the book to send them back. Measured here the copies
+//! leave the decoders at 3.5 to 5 times the owned throughput on Deribit and 1.5 to 2 times on OKX.
+//!
+//! Run with `cargo bench --bench decode`.
+use criterion::{
+    black_box,
+    BatchSize,
+    criterion_group,
+    criterion_main,
+    Criterion,
+    Throughput,
+};
+use serde_derive::Deserialize;
+use top_book::{
+    deribit,
+    okx,
+    orderbook::Level,
+};
+
+const DERIBIT_SNAPSHOT: &[u8] = include_bytes!("payloads/deribit_snapshot.json");
+const DERIBIT_CHANGE: &[u8] = include_bytes!("payloads/deribit_change.json");
+const OKX_SNAPSHOT: &[u8] = include_bytes!("payloads/okx_snapshot.json");
+const OKX_UPDATE: &[u8] = include_bytes!("payloads/okx_update.json");
+
+/// The previous parsing: every level an owned `Vec`, every string slot an owned `String`.
+mod owned {
+    use super::*;
+
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum AskBidValue {
+        // Never read, but allocated all the same.
+        #[allow(dead_code)]
+        Action(String),
+        PriceAmount(f64),
+    }
+
+    #[derive(Deserialize)]
+    struct DeribitData {
+        asks: Vec<Vec<AskBidValue>>,
+        bids: Vec<Vec<AskBidValue>>,
+    }
+
+    #[derive(Deserialize)]
+    struct DeribitParams {
+        data: DeribitData,
+    }
+
+    #[derive(Deserialize)]
+    struct DeribitMessage {
+        params: DeribitParams,
+    }
+
+    fn deribit_level(level: &[AskBidValue]) -> Level {
+        match level {
+            [_, AskBidValue::PriceAmount(price), AskBidValue::PriceAmount(size)] => Level { price: *price, size: *size },
+            _ => panic!("invalid level"),
+        }
+    }
+
+    pub fn deribit(data: &[u8]) -> (Vec<Level>, Vec<Level>) {
+        let message: DeribitMessage = serde_json::from_slice(data).unwrap();
+        let data = message.params.data;
+        (
+            data.asks.iter().map(|x| deribit_level(x)).collect(),
+            data.bids.iter().map(|x| deribit_level(x)).collect(),
+        )
+    }
+
+    #[derive(Deserialize)]
+    struct OKXData {
+        asks: Vec<Vec<String>>,
+        bids: Vec<Vec<String>>,
+    }
+
+    #[derive(Deserialize)]
+    struct OKXMessage {
+        data: Vec<OKXData>,
+    }
+
+    fn okx_level(level: &[String]) -> Level {
+        Level { price: level[0].parse().unwrap(), size: level[1].parse().unwrap() }
+    }
+
+    pub fn okx(data: &[u8]) -> (Vec<Level>, Vec<Level>) {
+        let message: OKXMessage = serde_json::from_slice(data).unwrap();
+        let data = &message.data[0];
+        (
+            data.asks.iter().map(|x| okx_level(x)).collect(),
+            data.bids.iter().map(|x| okx_level(x)).collect(),
+        )
+    }
+}
+
+fn deribit(c: &mut Criterion) {
+    for (name, payload) in [("deribit_snapshot", DERIBIT_SNAPSHOT), ("deribit_change", DERIBIT_CHANGE)] {
+        let mut group = c.benchmark_group(name);
+        group.throughput(Throughput::Bytes(payload.len() as u64));
+        // A fresh decoder each time, a reused one sees the same change twice and reports a gap.
+        group.bench_function("decoder", |b| {
+            b.iter_batched(
+                deribit::Decoder::default,
+                |mut decoder| decoder.decode(black_box(payload)).unwrap(),
+                BatchSize::SmallInput,
+            )
+        });
+        group.bench_function("owned", |b| b.iter(|| owned::deribit(black_box(payload))));
+        group.finish();
+    }
+}
+
+fn okx(c: &mut Criterion) {
+    for (name, payload) in [("okx_snapshot", OKX_SNAPSHOT), ("okx_update", OKX_UPDATE)] {
+        let mut group = c.benchmark_group(name);
+        group.throughput(Throughput::Bytes(payload.len() as u64));
+        // A fresh decoder each time, a reused one sees the same change twice and reports a gap.
+        group.bench_function("decoder", |b| {
+            b.iter_batched(
+                okx::Decoder::default,
+                |mut decoder| decoder.decode(black_box(payload)).unwrap(),
+                BatchSize::SmallInput,
+            )
+        });
+        group.bench_function("owned", |b| b.iter(|| owned::okx(black_box(payload))));
+        group.finish();
+    }
+}
+
+criterion_group!(benches, deribit, okx);
+criterion_main!(benches);
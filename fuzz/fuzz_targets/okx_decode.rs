@@ -0,0 +1,32 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use top_book::{
+    okx,
+    orderbook::{
+        Level,
+        Operation,
+        Orderbook,
+        Side,
+    },
+};
+
+fuzz_target!(|data: &[u8]| {
+    // Errors are fine, panics and levels the book cannot take are not.
+    let operation = match okx::Decoder::default().decode(data) {
+        Ok(Some(operation)) => operation,
+        _ => return,
+    };
+    if let Operation::Snapshot { asks, bids, .. } | Operation::Update { asks, bids, .. } = &operation {
+        assert!(asks.iter().chain(bids).all(Level::is_valid));
+    }
+
+    let mut orderbook = Orderbook::new(10);
+    orderbook.process(operation);
+    for side in [Side::Ask, Side::Bid] {
+        for level in orderbook.levels(side) {
+            assert!(level.price.is_finite());
+            assert!(level.source_size.values().all(|x| x.is_finite() && *x > 0.0));
+        }
+    }
+});
@@ -0,0 +1,324 @@
+use crate::orderbook::{
+    Level,
+    Orderbook,
+    Side,
+    Source,
+    EPSILON,
+};
+use std::{
+    collections::{
+        BTreeMap,
+        VecDeque,
+    },
+    time::Duration,
+};
+use tokio::time::Instant;
+
+/// Samples kept per scope however short the window, so a burst of updates stays bounded.
+const MAX_SAMPLES: usize = 4096;
+
+/// Book the signals are computed on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Scope {
+    Merged,
+    Venue(Source),
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Signals {
+    /// `(bids - asks) / (bids + asks)` over the top levels' sizes, from -1 to 1.
+    pub imbalance: Option<f64>,
+    /// Mid weighted by the top of book sizes, it leans towards the side more likely to trade.
+    pub microprice: Option<f64>,
+    /// Microprice over the top levels, each side priced at its average.
+    pub weighted_mid: Option<f64>,
+    /// Size within the depth band around the mid.
+    pub bid_depth: f64,
+    pub ask_depth: f64,
+    /// Cumulative size added per basis point away from the mid over the top levels, a least
+    /// squares fit.
+    pub bid_slope: Option<f64>,
+    pub ask_slope: Option<f64>,
+}
+
+/// Signals of a scope over the analytics window.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Rolling {
+    /// Book updates seen within the window.
+    pub samples: usize,
+    pub imbalance: Option<f64>,
+    pub microprice: Option<f64>,
+    /// Microprice move from the oldest sample of the window to the latest.
+    pub microprice_change: Option<f64>,
+}
+
+/// Microstructure signals computed once per book update, for the merged book and every venue,
+/// along with their average over a sliding time window.
+pub struct Analytics {
+    top_n: usize,
+    depth_bps: f64,
+    window: Duration,
+    history: BTreeMap<Scope, VecDeque<(Instant, Signals)>>,
+}
+
+fn distance_bps(price: f64, mid: f64) -> f64 {
+    (price - mid).abs() / mid * 10_000.0
+}
+
+/// Size and average price of `levels`.
+fn totals(levels: &[Level]) -> (f64, Option<f64>) {
+    let size: f64 = levels.iter().map(|x| x.size).sum();
+    if size <= EPSILON {
+        return (size, None);
+    }
+    (size, Some(levels.iter().map(|x| x.price * x.size).sum::<f64>() / size))
+}
+
+fn slope(levels: &[Level], mid: f64) -> Option<f64> {
+    if levels.len() < 2 {
+        return None;
+    }
+    let mut cumulative = 0.0;
+    let points: Vec<(f64, f64)> = levels.iter()
+        .map(|x| {
+            cumulative += x.size;
+            (distance_bps(x.price, mid), cumulative)
+        })
+        .collect();
+    let n = points.len() as f64;
+    let mean_x = points.iter().map(|x| x.0).sum::<f64>() / n;
+    let mean_y = points.iter().map(|x| x.1).sum::<f64>() / n;
+    let covariance: f64 = points.iter().map(|(x, y)| (x - mean_x) * (y - mean_y)).sum();
+    let variance: f64 = points.iter().map(|(x, _)| (x - mean_x).powi(2)).sum();
+    if variance <= EPSILON {
+        return None;
+    }
+    Some(covariance / variance)
+}
+
+fn mean(values: impl Iterator<Item=f64>) -> Option<f64> {
+    let (sum, count) = values.fold((0.0, 0), |(sum, count), x| (sum + x, count + 1));
+    (count > 0).then(|| sum / count as f64)
+}
+
+fn rolling(samples: &VecDeque<(Instant, Signals)>) -> Rolling {
+    let microprices = || samples.iter().filter_map(|(_, x)| x.microprice);
+    Rolling {
+        samples: samples.len(),
+        imbalance: mean(samples.iter().filter_map(|(_, x)| x.imbalance)),
+        microprice: mean(microprices()),
+        microprice_change: microprices().next().zip(microprices().next_back()).map(|(first, last)| last - first),
+    }
+}
+
+impl Analytics {
+    /// Signals look at the best `top_n` levels of a side, depth at `depth_bps` around the mid, and
+    /// are averaged over the last `window`.
+    pub fn new(top_n: usize, depth_bps: f64, window: Duration) -> Self {
+        Self {
+            top_n,
+            depth_bps,
+            window,
+            history: BTreeMap::new(),
+        }
+    }
+
+    fn levels(orderbook: &Orderbook, side: Side, scope: Scope) -> Vec<Level> {
+        match scope {
+            Scope::Merged => orderbook.levels(side).iter()
+                .map(|x| Level { price: x.price, size: x.size() })
+                .collect(),
+            Scope::Venue(source) => orderbook.source_levels(side, source).collect(),
+        }
+    }
+
+    pub fn compute(&self, orderbook: &Orderbook, scope: Scope) -> Signals {
+        let asks = Analytics::levels(orderbook, Side::Ask, scope);
+        let bids = Analytics::levels(orderbook, Side::Bid, scope);
+        let top_asks = &asks[..asks.len().min(self.top_n)];
+        let top_bids = &bids[..bids.len().min(self.top_n)];
+
+        let mut resp = Signals::default();
+        let (ask_size, ask_average) = totals(top_asks);
+        let (bid_size, bid_average) = totals(top_bids);
+        if ask_size + bid_size > EPSILON {
+            resp.imbalance = Some((bid_size - ask_size) / (bid_size + ask_size));
+        }
+        let (ask, bid) = match (asks.first(), bids.first()) {
+            (Some(ask), Some(bid)) => (ask, bid),
+            _ => return resp,
+        };
+
+        let mid = (ask.price + bid.price) / 2.0;
+        resp.microprice = Some((bid.price * ask.size + ask.price * bid.size) / (ask.size + bid.size));
+        if let (Some(ask_average), Some(bid_average)) = (ask_average, bid_average) {
+            resp.weighted_mid = Some((bid_average * ask_size + ask_average * bid_size) / (ask_size + bid_size));
+        }
+        let within = |x: &&Level| distance_bps(x.price, mid) <= self.depth_bps + EPSILON;
+        resp.ask_depth = asks.iter().take_while(within).map(|x| x.size).sum();
+        resp.bid_depth = bids.iter().take_while(within).map(|x| x.size).sum();
+        resp.ask_slope = slope(top_asks, mid);
+        resp.bid_slope = slope(top_bids, mid);
+        resp
+    }
+
+    /// Signals of the merged book, then of every venue it has heard from, as of `now`, with their
+    /// rolling values over the window ending at `now`.
+    pub fn observe(&mut self, orderbook: &Orderbook, now: Instant) -> Vec<(Scope, Signals, Rolling)> {
+        let venues = orderbook.statuses().map(|(source, _)| Scope::Venue(source));
+        let scopes: Vec<Scope> = std::iter::once(Scope::Merged).chain(venues).collect();
+        scopes.into_iter()
+            .map(|scope| {
+                let signals = self.compute(orderbook, scope);
+                let samples = self.history.entry(scope).or_default();
+                while samples.front().is_some_and(|(at, _)| now.duration_since(*at) > self.window)
+                    || samples.len() >= MAX_SAMPLES {
+                    samples.pop_front();
+                }
+                samples.push_back((now, signals.clone()));
+                (scope, signals, rolling(samples))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        analytics::{
+            Analytics,
+            Scope,
+        },
+        orderbook::{
+            Level,
+            Operation,
+            Orderbook,
+            Source,
+        },
+    };
+    use std::time::Duration;
+    use tokio::time::Instant;
+
+    const WINDOW: Duration = Duration::from_secs(1);
+
+    fn quote(orderbook: &mut Orderbook, bid_size: f64, ask_price: f64) {
+        orderbook.process(Operation::Snapshot {
+            asks: vec![Level { price: ask_price, size: 1.0 }],
+            bids: vec![Level { price: 99.0, size: bid_size }],
+            source: Source::OKX,
+            sequence: None,
+        });
+    }
+
+    fn orderbook() -> Orderbook {
+        let mut orderbook = Orderbook::new(20);
+        orderbook.process(Operation::Snapshot {
+            asks: vec![Level { price: 101.0, size: 1.0 }, Level { price: 102.0, size: 2.0 }],
+            bids: vec![Level { price: 99.0, size: 3.0 }, Level { price: 98.0, size: 3.0 }],
+            source: Source::OKX,
+            sequence: None,
+        });
+        orderbook.process(Operation::Snapshot {
+            asks: vec![Level { price: 100.5, size: 1.0 }],
+            bids: vec![Level { price: 99.0, size: 1.0 }],
+            source: Source::Deribit,
+            sequence: None,
+        });
+        orderbook
+    }
+
+    #[test]
+    fn should_compute_the_merged_signals() {
+        // Given
+        let analytics = Analytics::new(2, 100.0, WINDOW);
+
+        // When
+        let signals = analytics.compute(&orderbook(), Scope::Merged);
+
+        // Then
+        // Top asks are 100.5 x 1 and 101 x 1, top bids 99 x 4 and 98 x 3.
+        assert_eq!(Some(5.0 / 9.0), signals.imbalance);
+        assert_eq!(Some((99.0 * 1.0 + 100.5 * 4.0) / 5.0), signals.microprice);
+        let (ask_average, bid_average) = (100.75, (99.0 * 4.0 + 98.0 * 3.0) / 7.0);
+        let weighted_mid = (bid_average * 2.0 + ask_average * 7.0) / 9.0;
+        assert!((signals.weighted_mid.unwrap() - weighted_mid).abs() < 1e-9);
+        // The mid is 99.75, 100 bps away are 98.7525 and 100.7475.
+        assert_eq!(1.0, signals.ask_depth);
+        assert_eq!(4.0, signals.bid_depth);
+    }
+
+    #[test]
+    fn should_compute_the_signals_of_a_venue() {
+        // Given
+        let analytics = Analytics::new(5, 100.0, WINDOW);
+
+        // When
+        let signals = analytics.compute(&orderbook(), Scope::Venue(Source::OKX));
+
+        // Then
+        assert_eq!(Some(1.0 / 3.0), signals.imbalance);
+        assert_eq!(Some(100.5), signals.microprice);
+        assert_eq!((1.0, 3.0), (signals.ask_depth, signals.bid_depth));
+        // Cumulative asks are 1 at 100 bps and 3 at 200 bps.
+        assert!((signals.ask_slope.unwrap() - 0.02).abs() < 1e-9);
+    }
+
+    #[test]
+    fn should_report_every_scope() {
+        // Given
+        let mut analytics = Analytics::new(5, 10.0, WINDOW);
+
+        // When
+        let scopes: Vec<Scope> = analytics.observe(&orderbook(), Instant::now()).into_iter()
+            .map(|(x, _, _)| x)
+            .collect();
+
+        // Then
+        assert_eq!(vec![Scope::Merged, Scope::Venue(Source::Deribit), Scope::Venue(Source::OKX)], scopes);
+    }
+
+    #[test]
+    fn should_leave_out_prices_of_a_one_sided_book() {
+        // Given
+        let mut orderbook = Orderbook::new(20);
+        orderbook.process(Operation::Snapshot {
+            asks: vec![Level { price: 101.0, size: 1.0 }],
+            bids: vec![],
+            source: Source::OKX,
+            sequence: None,
+        });
+
+        // When
+        let signals = Analytics::new(5, 10.0, WINDOW).compute(&orderbook, Scope::Merged);
+
+        // Then
+        assert_eq!(Some(-1.0), signals.imbalance);
+        assert_eq!(None, signals.microprice);
+        assert_eq!(0.0, signals.ask_depth);
+    }
+
+    #[test]
+    fn should_roll_the_signals_over_the_window() {
+        // Given
+        let mut analytics = Analytics::new(5, 10.0, WINDOW);
+        let mut orderbook = Orderbook::new(20);
+        let start = Instant::now();
+
+        // When
+        quote(&mut orderbook, 1.0, 101.0);
+        let first = analytics.observe(&orderbook, start).remove(0).2;
+        quote(&mut orderbook, 3.0, 103.0);
+        let second = analytics.observe(&orderbook, start + Duration::from_millis(500)).remove(0).2;
+        quote(&mut orderbook, 1.0, 101.0);
+        let third = analytics.observe(&orderbook, start + Duration::from_millis(1_200)).remove(0).2;
+
+        // Then
+        // Imbalances are 0, then 0.5, then 0 again, microprices 100, 102 and 100.
+        assert_eq!((1, Some(0.0), Some(100.0), Some(0.0)),
+                   (first.samples, first.imbalance, first.microprice, first.microprice_change));
+        assert_eq!((2, Some(0.25), Some(101.0), Some(2.0)),
+                   (second.samples, second.imbalance, second.microprice, second.microprice_change));
+        assert_eq!((2, Some(0.25), Some(101.0), Some(-2.0)),
+                   (third.samples, third.imbalance, third.microprice, third.microprice_change));
+    }
+}
@@ -0,0 +1,275 @@
+use crate::{
+    config::FeesConfig,
+    fees::{
+        effective_price,
+        Liquidity,
+    },
+    orderbook::{
+        Orderbook,
+        Side,
+        Source,
+        VenueStatus,
+        EPSILON,
+    },
+};
+use std::{
+    collections::BTreeMap,
+    time::Duration,
+};
+use tokio::time::Instant;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArbState {
+    /// The crossing lasted the debounce period.
+    Open,
+    /// The crossing is gone, the signal carries what was last seen.
+    Closed,
+}
+
+/// Buying the best ask of `buy` and selling the best bid of `sell` pays after taker fees.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ArbSignal {
+    pub state: ArbState,
+    pub buy: Source,
+    pub sell: Source,
+    pub ask: f64,
+    pub bid: f64,
+    /// Smaller of the two top of book sizes.
+    pub size: f64,
+    /// Profit of trading `size` on both venues, fees paid.
+    pub profit: f64,
+    /// Time since the crossing appeared.
+    pub duration: Duration,
+}
+
+struct Opportunity {
+    started: Instant,
+    reported: bool,
+    last: ArbSignal,
+}
+
+/// Watches the per-venue top of book for crossings worth trading after fees.
+pub struct ArbDetector {
+    fees: FeesConfig,
+    debounce: Duration,
+    opportunities: BTreeMap<(Source, Source), Opportunity>,
+}
+
+impl ArbDetector {
+    pub fn new(fees: FeesConfig, debounce: Duration) -> Self {
+        Self {
+            fees,
+            debounce,
+            opportunities: BTreeMap::new(),
+        }
+    }
+
+    /// Looks at the book as of `now`, to be called after every operation.
+    ///
+    /// A crossing is reported `Open` once it lasted the debounce period, and `Closed` when it goes
+    /// away. Crossings shorter than the debounce period are never reported.
+    ///
+    /// Only venues that are up take part, the levels of a resyncing venue may be stale. The size of
+    /// our own resting orders is left out, a crossing with our own quotes is not one we can trade.
+    pub fn observe(&mut self, orderbook: &Orderbook, now: Instant) -> Vec<ArbSignal> {
+        let sources: Vec<Source> = orderbook.statuses()
+            .filter(|(_, status)| *status == VenueStatus::Up)
+            .map(|(source, _)| source)
+            .collect();
+        let mut resp = vec![];
+        let mut crossed = vec![];
+        for buy in &sources {
+            for sell in sources.iter().filter(|x| *x != buy) {
+                if let Some(signal) = self.crossing(orderbook, *buy, *sell) {
+                    crossed.push((*buy, *sell));
+                    let opportunity = self.opportunities.entry((*buy, *sell)).or_insert(Opportunity {
+                        started: now,
+                        reported: false,
+                        last: signal.clone(),
+                    });
+                    opportunity.last = ArbSignal { duration: now - opportunity.started, ..signal };
+                    if !opportunity.reported && opportunity.last.duration >= self.debounce {
+                        opportunity.reported = true;
+                        resp.push(opportunity.last.clone());
+                    }
+                }
+            }
+        }
+
+        let closed: Vec<(Source, Source)> = self.opportunities.keys()
+            .filter(|x| !crossed.contains(x))
+            .copied()
+            .collect();
+        for key in closed {
+            let opportunity = self.opportunities.remove(&key).expect("key was just listed");
+            if opportunity.reported {
+                resp.push(ArbSignal {
+                    state: ArbState::Closed,
+                    duration: now - opportunity.started,
+                    ..opportunity.last
+                });
+            }
+        }
+        resp
+    }
+
+    fn crossing(&self, orderbook: &Orderbook, buy: Source, sell: Source) -> Option<ArbSignal> {
+        let ask = orderbook.best_external_level(Side::Ask, buy)?;
+        let bid = orderbook.best_external_level(Side::Bid, sell)?;
+        let pay = effective_price(Side::Ask, ask.price, self.fees.schedule(buy).rate(Liquidity::Taker));
+        let receive = effective_price(Side::Bid, bid.price, self.fees.schedule(sell).rate(Liquidity::Taker));
+        if receive - pay <= EPSILON {
+            return None;
+        }
+        let size = ask.size.min(bid.size);
+        Some(ArbSignal {
+            state: ArbState::Open,
+            buy,
+            sell,
+            ask: ask.price,
+            bid: bid.price,
+            size,
+            profit: (receive - pay) * size,
+            duration: Duration::ZERO,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        arb::{
+            ArbDetector,
+            ArbState,
+        },
+        config::{
+            FeeSchedule,
+            FeesConfig,
+        },
+        orderbook::{
+            Level,
+            Operation,
+            Orderbook,
+            OwnOrder,
+            Side,
+            Source,
+            VenueStatus,
+        },
+    };
+    use std::time::Duration;
+    use tokio::time::Instant;
+
+    fn fees() -> FeesConfig {
+        FeesConfig {
+            deribit: FeeSchedule { maker_bps: 0.0, taker_bps: 5.0 },
+            okx: FeeSchedule { maker_bps: 0.0, taker_bps: 5.0 },
+        }
+    }
+
+    fn quote(orderbook: &mut Orderbook, source: Source, bid: f64, ask: f64) {
+        orderbook.process(Operation::Snapshot {
+            asks: vec![Level { price: ask, size: 2.0 }],
+            bids: vec![Level { price: bid, size: 1.0 }],
+            source,
+            sequence: None,
+        });
+    }
+
+    #[test]
+    fn should_open_and_close_a_debounced_signal() {
+        // Given
+        let mut detector = ArbDetector::new(fees(), Duration::from_millis(100));
+        let mut orderbook = Orderbook::new(5);
+        let start = Instant::now();
+        quote(&mut orderbook, Source::Deribit, 9_990.0, 10_000.0);
+        quote(&mut orderbook, Source::OKX, 10_020.0, 10_030.0);
+
+        // When
+        let first = detector.observe(&orderbook, start);
+        let opened = detector.observe(&orderbook, start + Duration::from_millis(150));
+        quote(&mut orderbook, Source::OKX, 9_995.0, 10_005.0);
+        let closed = detector.observe(&orderbook, start + Duration::from_millis(400));
+
+        // Then
+        assert!(first.is_empty());
+        assert_eq!(1, opened.len());
+        assert_eq!(ArbState::Open, opened[0].state);
+        assert_eq!((Source::Deribit, Source::OKX), (opened[0].buy, opened[0].sell));
+        assert_eq!(1.0, opened[0].size);
+        assert!((opened[0].profit - (10_020.0 * 0.9995 - 10_000.0 * 1.0005)).abs() < 1e-6);
+        assert_eq!(1, closed.len());
+        assert_eq!(ArbState::Closed, closed[0].state);
+        assert_eq!(Duration::from_millis(400), closed[0].duration);
+    }
+
+    #[test]
+    fn should_ignore_a_crossing_eaten_by_fees() {
+        // Given
+        let mut detector = ArbDetector::new(fees(), Duration::ZERO);
+        let mut orderbook = Orderbook::new(5);
+        quote(&mut orderbook, Source::Deribit, 9_990.0, 10_000.0);
+        quote(&mut orderbook, Source::OKX, 10_005.0, 10_015.0);
+
+        // When
+        let signals = detector.observe(&orderbook, Instant::now());
+
+        // Then
+        assert!(signals.is_empty());
+    }
+
+    #[test]
+    fn should_not_report_a_flickering_crossing() {
+        // Given
+        let mut detector = ArbDetector::new(fees(), Duration::from_millis(100));
+        let mut orderbook = Orderbook::new(5);
+        let start = Instant::now();
+        quote(&mut orderbook, Source::Deribit, 9_990.0, 10_000.0);
+        quote(&mut orderbook, Source::OKX, 10_020.0, 10_030.0);
+
+        // When
+        let first = detector.observe(&orderbook, start);
+        quote(&mut orderbook, Source::OKX, 9_995.0, 10_005.0);
+        let second = detector.observe(&orderbook, start + Duration::from_millis(50));
+
+        // Then
+        assert!(first.is_empty());
+        assert!(second.is_empty());
+    }
+
+    #[test]
+    fn should_not_trade_on_a_resyncing_venue() {
+        // Given
+        let mut detector = ArbDetector::new(fees(), Duration::ZERO);
+        let mut orderbook = Orderbook::new(5);
+        quote(&mut orderbook, Source::Deribit, 9_990.0, 10_000.0);
+        quote(&mut orderbook, Source::OKX, 10_020.0, 10_030.0);
+
+        // When
+        orderbook.process(Operation::Status { status: VenueStatus::Resyncing, source: Source::OKX });
+        let signals = detector.observe(&orderbook, Instant::now());
+
+        // Then
+        assert!(signals.is_empty());
+        assert_eq!(Some(10_020.0), orderbook.best_bid());
+    }
+
+    #[test]
+    fn should_not_trade_against_our_own_quote() {
+        // Given
+        let mut detector = ArbDetector::new(fees(), Duration::ZERO);
+        let mut orderbook = Orderbook::new(5);
+        quote(&mut orderbook, Source::Deribit, 9_990.0, 10_000.0);
+        quote(&mut orderbook, Source::OKX, 10_020.0, 10_030.0);
+
+        // When
+        orderbook.update_own_orders(Source::Deribit, vec![
+            OwnOrder { id: "ask".to_string(), side: Side::Ask, price: 10_000.0, size: 2.0 },
+        ], true);
+        let signals = detector.observe(&orderbook, Instant::now());
+        orderbook.update_own_orders(Source::Deribit, vec![], true);
+
+        // Then
+        assert!(signals.is_empty());
+        assert_eq!(1, detector.observe(&orderbook, Instant::now()).len());
+    }
+}
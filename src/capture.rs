@@ -0,0 +1,539 @@
+use crate::{
+    connector::{
+        ErrorPolicy,
+        Shutdown,
+    },
+    deribit::{
+        self,
+        DeribitError,
+    },
+    okx::{
+        self,
+        OKXError,
+    },
+    orderbook::Source,
+    queue::OperationSender,
+};
+use flate2::{
+    bufread::GzDecoder,
+    read::MultiGzDecoder,
+    write::GzEncoder,
+    Compression,
+};
+use serde_derive::{
+    Deserialize,
+    Serialize,
+};
+use std::{
+    fs::{
+        self,
+        File,
+        OpenOptions,
+    },
+    io::{
+        self,
+        BufRead,
+        BufReader,
+        BufWriter,
+        ErrorKind,
+        Read,
+        Seek,
+        Write,
+    },
+    num::ParseIntError,
+    path::{
+        Path,
+        PathBuf,
+    },
+    sync::mpsc::{
+        self as std_mpsc,
+        RecvTimeoutError,
+        TrySendError,
+    },
+    time::{
+        Duration,
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+use tokio::{
+    sync::mpsc,
+    time::Instant,
+};
+
+/// Records read ahead of the replay.
+const REPLAY_BUFFER: usize = 1024;
+/// Records waiting for the writer, past them new ones are dropped rather than block a connector.
+const RECORD_BUFFER: usize = 4096;
+/// How long a written record may sit in the encoder before it is sync-flushed to the file.
+const FLUSH_INTERVAL: Duration = Duration::from_millis(200);
+
+#[derive(Debug)]
+pub enum CaptureError {
+    Closed,
+    Deribit(DeribitError),
+    /// The writer fell behind, the record was dropped.
+    Full,
+    Io(io::Error),
+    OKX(OKXError),
+    PayloadDecode(base64::DecodeError),
+    RecordParse(serde_json::Error),
+    RecordSerialize(serde_json::Error),
+    /// The offset in the session file of a capture is not a number.
+    SessionParse(ParseIntError),
+}
+
+/// The bytes of a frame, kept as they came.
+#[derive(Serialize, Deserialize)]
+enum Payload {
+    /// UTF-8, as every JSON frame is.
+    #[serde(rename = "payload")]
+    Text(String),
+    /// Anything else, in base64.
+    #[serde(rename = "payload_base64")]
+    Binary(String),
+}
+
+impl Payload {
+    fn new(bytes: &[u8]) -> Self {
+        match std::str::from_utf8(bytes) {
+            Ok(text) => Payload::Text(text.to_string()),
+            Err(_) => Payload::Binary(base64::encode(bytes)),
+        }
+    }
+
+    fn into_bytes(self) -> Result<Vec<u8>, CaptureError> {
+        match self {
+            Payload::Text(text) => Ok(text.into_bytes()),
+            Payload::Binary(encoded) => base64::decode(encoded).map_err(CaptureError::PayloadDecode),
+        }
+    }
+}
+
+/// One raw websocket frame as it was received from a venue.
+#[derive(Serialize, Deserialize)]
+struct CaptureRecord {
+    /// Microseconds since the Unix epoch.
+    received_at_us: u64,
+    source: Source,
+    #[serde(flatten)]
+    payload: Payload,
+}
+
+fn now_us() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_micros() as u64)
+        .unwrap_or_default()
+}
+
+/// Path of the file holding where the open session of the capture at `path` starts.
+fn session_path(path: &Path) -> PathBuf {
+    let mut session = path.as_os_str().to_os_string();
+    session.push(".session");
+    PathBuf::from(session)
+}
+
+enum Command {
+    Record(CaptureRecord),
+    Finish(std_mpsc::Sender<Result<(), CaptureError>>),
+}
+
+/// Appends raw frames to a gzip capture file, one JSON record per line.
+///
+/// Every session opens a new gzip member at the end of the file, its offset is kept in a session
+/// file next to the capture until the member is finished. Records are encoded on a writer thread
+/// and sync-flushed at most [`FLUSH_INTERVAL`] apart, so a crash loses little more: the member
+/// it leaves unfinished is repaired by the next `open`.
+#[derive(Clone)]
+pub struct Recorder {
+    records: std_mpsc::SyncSender<Command>,
+}
+
+impl Recorder {
+    pub fn open(path: &Path) -> Result<Self, CaptureError> {
+        let mut file = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(path)
+            .map_err(CaptureError::Io)?;
+        let session = session_path(path);
+        Recorder::repair(&mut file, &session)?;
+        let start = file.seek(io::SeekFrom::End(0)).map_err(CaptureError::Io)?;
+        fs::write(&session, start.to_string()).map_err(CaptureError::Io)?;
+
+        let (records, received) = std_mpsc::sync_channel(RECORD_BUFFER);
+        let writer = GzEncoder::new(BufWriter::new(file), Compression::default());
+        std::thread::spawn(move || write_records(writer, received, session));
+        Ok(Self { records })
+    }
+
+    /// Finishes the member a crashed session left behind, a member appended after it would
+    /// otherwise make the whole file unreadable.
+    ///
+    /// Only that member is read, from the offset its session file kept. Its complete records are
+    /// written back as a finished member, whatever follows the last of them is dropped.
+    fn repair(file: &mut File, session: &Path) -> Result<(), CaptureError> {
+        let start = match fs::read_to_string(session) {
+            Ok(start) => start.trim().parse().map_err(CaptureError::SessionParse)?,
+            Err(err) if err.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(err) => return Err(CaptureError::Io(err)),
+        };
+        Recorder::repair_from(file, start).map_err(CaptureError::Io)?;
+        fs::remove_file(session).map_err(CaptureError::Io)
+    }
+
+    fn repair_from(file: &mut File, start: u64) -> io::Result<()> {
+        let start = start.min(file.metadata()?.len());
+        file.seek(io::SeekFrom::Start(start))?;
+        let mut member = GzDecoder::new(BufReader::new(&*file));
+        let mut complete = vec![];
+        let mut pending = vec![];
+        let mut chunk = [0; 8192];
+        loop {
+            let read = match member.read(&mut chunk) {
+                Ok(0) | Err(_) => break,
+                Ok(read) => read,
+            };
+            pending.extend_from_slice(&chunk[..read]);
+            if let Some(end) = pending.iter().rposition(|x| *x == b'\n') {
+                complete.extend(pending.drain(..=end));
+            }
+        }
+
+        file.set_len(start)?;
+        if !complete.is_empty() {
+            let mut repaired = GzEncoder::new(vec![], Compression::default());
+            repaired.write_all(&complete)?;
+            file.write_all(&repaired.finish()?)?;
+        }
+        file.flush()
+    }
+
+    /// Hands the frame to the writer, without waiting for it to be written.
+    pub fn record(&self, source: Source, payload: &[u8]) -> Result<(), CaptureError> {
+        let record = CaptureRecord { received_at_us: now_us(), source, payload: Payload::new(payload) };
+        self.records.try_send(Command::Record(record)).map_err(|err| match err {
+            TrySendError::Full(_) => CaptureError::Full,
+            TrySendError::Disconnected(_) => CaptureError::Closed,
+        })
+    }
+
+    /// Writes what is left, ends the gzip member and flushes the file, later records fail with
+    /// `Closed`. The first error of the writer, if any, is returned here.
+    pub fn finish(&self) -> Result<(), CaptureError> {
+        let (reply, finished) = std_mpsc::channel();
+        if self.records.send(Command::Finish(reply)).is_err() {
+            return Ok(());
+        }
+        finished.recv().unwrap_or(Err(CaptureError::Closed))
+    }
+}
+
+/// Runs the writer of a [`Recorder`] until it is finished, or all its copies are dropped.
+///
+/// After an error the records are dropped, the error waits for `finish`.
+fn write_records(
+    writer: GzEncoder<BufWriter<File>>, records: std_mpsc::Receiver<Command>, session: PathBuf,
+) {
+    let mut writer = Some(writer);
+    let mut failed = None;
+    let mut flushed_at = std::time::Instant::now();
+    let mut dirty = false;
+    loop {
+        let written = match records.recv_timeout(FLUSH_INTERVAL) {
+            Ok(Command::Record(record)) => match writer.as_mut() {
+                Some(writer) => write_record(writer, &record).map(|_| dirty = true),
+                None => continue,
+            },
+            Ok(Command::Finish(reply)) => {
+                let _ = reply.send(finish_writer(writer, failed, &session));
+                return;
+            }
+            Err(RecvTimeoutError::Timeout) => Ok(()),
+            Err(RecvTimeoutError::Disconnected) => {
+                let _ = finish_writer(writer, failed, &session);
+                return;
+            }
+        };
+        let flush_due = dirty && flushed_at.elapsed() >= FLUSH_INTERVAL;
+        let written = written.and_then(|_| match writer.as_mut() {
+            Some(writer) if flush_due => writer.flush().map_err(CaptureError::Io),
+            _ => Ok(()),
+        });
+        if flush_due {
+            (flushed_at, dirty) = (std::time::Instant::now(), false);
+        }
+        if let Err(err) = written {
+            writer = None;
+            failed.get_or_insert(err);
+        }
+    }
+}
+
+fn write_record(writer: &mut GzEncoder<BufWriter<File>>, record: &CaptureRecord) -> Result<(), CaptureError> {
+    let mut line = serde_json::to_vec(record).map_err(CaptureError::RecordSerialize)?;
+    line.push(b'\n');
+    writer.write_all(&line).map_err(CaptureError::Io)
+}
+
+/// Ends the member of the session and drops its session file, the capture needs no repair then.
+fn finish_writer(
+    writer: Option<GzEncoder<BufWriter<File>>>, failed: Option<CaptureError>, session: &Path,
+) -> Result<(), CaptureError> {
+    if let Some(err) = failed {
+        return Err(err);
+    }
+    if let Some(writer) = writer {
+        writer.finish().and_then(|mut file| file.flush()).map_err(CaptureError::Io)?;
+    }
+    fs::remove_file(session).map_err(CaptureError::Io)
+}
+
+/// Feeds a capture file back through the venue parsers.
+///
+/// `speed` scales the original inter-frame delays, `2.0` replays twice as fast, while `0.0`
+/// replays without waiting at all. The replay stops early when `shutdown` fires.
+pub async fn replay(
+    path: &Path, speed: f64, sender: OperationSender, shutdown: Shutdown,
+) -> Result<(), CaptureError> {
+    let (records, received) = mpsc::channel(REPLAY_BUFFER);
+    let path = path.to_path_buf();
+    let reader = tokio::task::spawn_blocking(move || read_records(&path, records));
+    let replayed = replay_records(received, speed, sender, shutdown).await;
+    // The reader stops on its own once the replay dropped its end of the channel.
+    let read = reader.await.map_err(|err| CaptureError::Io(io::Error::other(err)))?;
+    replayed.and(read)
+}
+
+/// Reads the records of a capture file into `records`, until the file or the replay ends.
+fn read_records(path: &Path, records: mpsc::Sender<CaptureRecord>) -> Result<(), CaptureError> {
+    let file = File::open(path).map_err(CaptureError::Io)?;
+    let mut reader = BufReader::new(MultiGzDecoder::new(file));
+    let mut line = String::new();
+    loop {
+        line.clear();
+        match reader.read_line(&mut line) {
+            Ok(0) => return Ok(()),
+            Ok(_) => {}
+            // A capture cut short by a crash ends in a truncated gzip member.
+            Err(err) if err.kind() == ErrorKind::UnexpectedEof => return Ok(()),
+            Err(err) => return Err(CaptureError::Io(err)),
+        }
+        if line.trim().is_empty() {
+            continue;
+        }
+
+        let record: CaptureRecord = serde_json::from_str(&line).map_err(CaptureError::RecordParse)?;
+        if records.blocking_send(record).is_err() {
+            return Ok(());
+        }
+    }
+}
+
+async fn replay_records(
+    mut records: mpsc::Receiver<CaptureRecord>, speed: f64, sender: OperationSender, mut shutdown: Shutdown,
+) -> Result<(), CaptureError> {
+    let started = Instant::now();
+    let mut first_received_at = None;
+    let (mut deribit_decoder, mut okx_decoder) = (deribit::Decoder::default(), okx::Decoder::default());
+    while !shutdown.is_triggered() {
+        let record = tokio::select! {
+            record = records.recv() => match record {
+                Some(record) => record,
+                None => break,
+            },
+            _ = shutdown.wait() => break,
+        };
+        let first_received_at = *first_received_at.get_or_insert(record.received_at_us);
+        if speed > 0.0 {
+            let elapsed_us = record.received_at_us.saturating_sub(first_received_at) as f64 / speed;
+            tokio::select! {
+                _ = tokio::time::sleep_until(started + Duration::from_micros(elapsed_us as u64)) => {}
+                _ = shutdown.wait() => break,
+            }
+        }
+
+        // The capture already holds whatever the live session did to recover, so only errors that
+        // stop a connector stop the replay.
+        let payload = record.payload.into_bytes()?;
+        match record.source {
+            Source::Deribit => {
+                if let Err(err) = deribit::process_message(&payload, &mut deribit_decoder, &sender).await {
+                    if deribit::report_error(&err).await == ErrorPolicy::Stop {
+                        return Err(CaptureError::Deribit(err));
+                    }
+                }
+            }
+            Source::OKX => {
+                if let Err(err) = okx::process_message(&payload, &mut okx_decoder, &sender).await {
+                    if okx::report_error(&err).await == ErrorPolicy::Stop {
+                        return Err(CaptureError::OKX(err));
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        capture::{
+            read_records,
+            replay,
+            session_path,
+            CaptureRecord,
+            Payload,
+            Recorder,
+        },
+        config::QueuePolicy,
+        connector::shutdown,
+        orderbook::{
+            Operation,
+            Source,
+        },
+        queue,
+    };
+    use flate2::{
+        write::GzEncoder,
+        Compression,
+    };
+    use std::{
+        io::Write,
+        path::PathBuf,
+    };
+    use tokio::sync::mpsc;
+
+    fn capture_path(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("top-book-{}-{}.jsonl.gz", name, std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let _ = std::fs::remove_file(session_path(&path));
+        path
+    }
+
+    #[tokio::test]
+    async fn should_replay_recorded_frames() {
+        // Given
+        let path = capture_path("replay");
+        let recorder = Recorder::open(&path).unwrap();
+        recorder.record(
+            Source::OKX,
+            br#"{"arg":{"channel":"books","instId":"BTC-USDT"},"action":"snapshot","data":[{"asks":[["23811","0.4","0","5"]],"bids":[["23810","0.2","0","1"]]}]}"#,
+        ).unwrap();
+        recorder.record(
+            Source::Deribit,
+            br#"{"jsonrpc":"2.0","method":"subscription","params":{"channel":"book.BTC-PERPETUAL.100ms","data":{"type":"change","asks":[["new",23920.0,2260.0]],"bids":[]}}}"#,
+        ).unwrap();
+        recorder.finish().unwrap();
+
+        // When
+        let (sender, mut receiver) = queue::channel(16, QueuePolicy::Block);
+        replay(&path, 0.0, sender, shutdown().1).await.unwrap();
+
+        // Then
+        match receiver.recv().await {
+            Some(Operation::Snapshot { asks, bids, source, .. }) => {
+                assert_eq!(Source::OKX, source);
+                assert_eq!(23811.0, asks[0].price);
+                assert_eq!(0.2, bids[0].size);
+            }
+            _ => panic!("not a snapshot"),
+        }
+        match receiver.recv().await {
+            Some(Operation::Update { asks, source, .. }) => {
+                assert_eq!(Source::Deribit, source);
+                assert_eq!(2260.0, asks[0].size);
+            }
+            _ => panic!("not an update"),
+        }
+        assert!(receiver.recv().await.is_none());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn should_append_sessions_to_the_same_capture() {
+        // Given
+        let path = capture_path("append");
+        let frame = br#"{"arg":{"channel":"books","instId":"BTC-USDT"},"action":"update","data":[{"asks":[],"bids":[["23810","0","0","0"]]}]}"#;
+        for _ in 0..2 {
+            let recorder = Recorder::open(&path).unwrap();
+            recorder.record(Source::OKX, frame).unwrap();
+            recorder.finish().unwrap();
+        }
+
+        // When
+        let (sender, mut receiver) = queue::channel(16, QueuePolicy::Block);
+        replay(&path, 0.0, sender, shutdown().1).await.unwrap();
+
+        // Then
+        let mut replayed = 0;
+        while receiver.recv().await.is_some() {
+            replayed += 1;
+        }
+        assert_eq!(2, replayed);
+        assert!(!session_path(&path).exists());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[tokio::test]
+    async fn should_replay_earlier_sessions_after_a_crash() {
+        // Given
+        let path = capture_path("crash");
+        let frame = br#"{"arg":{"channel":"books","instId":"BTC-USDT"},"action":"update","data":[{"asks":[],"bids":[["23810","0","0","0"]]}]}"#;
+        let first = Recorder::open(&path).unwrap();
+        first.record(Source::OKX, frame).unwrap();
+        first.finish().unwrap();
+        // A crash never finishes the member, and may cut the last record short.
+        let start = std::fs::metadata(&path).unwrap().len();
+        std::fs::write(session_path(&path), start.to_string()).unwrap();
+        let record = CaptureRecord { received_at_us: 1, source: Source::OKX, payload: Payload::new(frame) };
+        let mut line = serde_json::to_vec(&record).unwrap();
+        line.push(b'\n');
+        let file = std::fs::OpenOptions::new().append(true).open(&path).unwrap();
+        let mut crashed = GzEncoder::new(file, Compression::default());
+        crashed.write_all(&line).unwrap();
+        crashed.write_all(&line[..line.len() / 2]).unwrap();
+        crashed.flush().unwrap();
+        std::mem::forget(crashed);
+
+        // When
+        let recorder = Recorder::open(&path).unwrap();
+        recorder.record(Source::OKX, frame).unwrap();
+        recorder.finish().unwrap();
+        let (sender, mut receiver) = queue::channel(16, QueuePolicy::Block);
+        replay(&path, 0.0, sender, shutdown().1).await.unwrap();
+
+        // Then
+        let mut replayed = 0;
+        while receiver.recv().await.is_some() {
+            replayed += 1;
+        }
+        // The first session, the complete record of the crashed one and the next session.
+        assert_eq!(3, replayed);
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn should_keep_binary_frames_as_they_came() {
+        // Given
+        let path = capture_path("binary");
+        let frame = [0x1f, 0x8b, 0xff, 0x00, b'\n'];
+        let recorder = Recorder::open(&path).unwrap();
+        recorder.record(Source::Deribit, &frame).unwrap();
+        recorder.finish().unwrap();
+
+        // When
+        let (records, mut received) = mpsc::channel(1);
+        read_records(&path, records).unwrap();
+
+        // Then
+        let record = received.try_recv().unwrap();
+        assert!(matches!(record.payload, Payload::Binary(_)));
+        assert_eq!(frame.to_vec(), record.payload.into_bytes().unwrap());
+        let _ = std::fs::remove_file(&path);
+    }
+}
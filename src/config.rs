@@ -0,0 +1,904 @@
+use crate::orderbook::Source;
+use serde_derive::Deserialize;
+use std::{
+    fmt::{
+        Display,
+        Formatter,
+    },
+    io,
+    net::SocketAddr,
+    path::{
+        Path,
+        PathBuf,
+    },
+    str::FromStr,
+};
+use url::Url;
+
+const ENV_PREFIX: &str = "TOP_BOOK_";
+
+#[derive(Debug)]
+pub enum ConfigError {
+    Read(PathBuf, io::Error),
+    Parse(toml::de::Error),
+    InvalidEnv { name: String, value: String },
+    NoVenueEnabled,
+    ZeroDepth,
+    EmptyInstrument(&'static str),
+    InvalidUrl(&'static str, url::ParseError),
+    InvalidKeepalive(&'static str),
+    /// Only one of `client_id` and `client_secret` is set.
+    IncompleteCredentials(&'static str),
+    EmptyServiceName,
+    InvalidSamplingRatio(f64),
+    ZeroChannelCapacity,
+    InvalidBackoff,
+    ZeroPersistenceInterval,
+    InvalidFee(&'static str),
+    InvalidAnalytics,
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ConfigError::Read(path, err) => write!(f, "could not read {}: {}", path.display(), err),
+            ConfigError::Parse(err) => write!(f, "invalid configuration: {}", err),
+            ConfigError::InvalidEnv { name, value } => write!(f, "invalid value {:?} for {}", value, name),
+            ConfigError::NoVenueEnabled => f.write_str("at least one venue must be enabled"),
+            ConfigError::ZeroDepth => f.write_str("depth must be greater than zero"),
+            ConfigError::EmptyInstrument(venue) => write!(f, "{}.instrument must not be empty", venue),
+            ConfigError::InvalidUrl(venue, err) => write!(f, "{}.url is not a valid url: {}", venue, err),
+            ConfigError::InvalidKeepalive(venue) => write!(
+                f, "{} keepalive interval must be within the venue limits and shorter than idle_timeout_secs", venue,
+            ),
+            ConfigError::IncompleteCredentials(venue) => write!(
+                f, "{}.client_id and {}.client_secret must be set together", venue, venue,
+            ),
+            ConfigError::EmptyServiceName => f.write_str("telemetry.service_name must not be empty"),
+            ConfigError::InvalidSamplingRatio(ratio) => write!(
+                f, "telemetry.sampling_ratio must be between 0 and 1, got {}", ratio,
+            ),
+            ConfigError::ZeroChannelCapacity => f.write_str("channel.capacity must be greater than zero"),
+            ConfigError::InvalidBackoff => f.write_str(
+                "supervisor.backoff_initial_ms must be greater than zero and at most supervisor.backoff_max_ms",
+            ),
+            ConfigError::ZeroPersistenceInterval => f.write_str("persistence.interval_secs must be greater than zero"),
+            ConfigError::InvalidFee(venue) => write!(
+                f, "{} fees must be finite and strictly between -10000 and 10000 bps", venue,
+            ),
+            ConfigError::InvalidAnalytics => f.write_str(
+                "analytics.top_n and analytics.window_ms must be greater than zero and analytics.depth_bps finite and \
+                 not negative",
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// A credential, kept out of `Debug` output so configurations can be logged.
+#[derive(Clone, Default, Deserialize)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(secret: impl Into<String>) -> Self {
+        Self(secret.into())
+    }
+
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl std::fmt::Debug for Secret {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.write_str(if self.0.is_empty() { "\"\"" } else { "\"***\"" })
+    }
+}
+
+/// How `public/auth` proves the API key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AuthMethod {
+    /// Sends the client secret itself.
+    ClientCredentials,
+    /// Sends an HMAC-SHA256 signature made with the client secret, which never leaves the process.
+    ClientSignature,
+}
+
+impl FromStr for AuthMethod {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "client-credentials" => Ok(AuthMethod::ClientCredentials),
+            "client-signature" => Ok(AuthMethod::ClientSignature),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DeribitConfig {
+    pub enabled: bool,
+    pub url: String,
+    pub instrument: String,
+    /// Notification interval of the `book` channel, e.g. `100ms` or `raw`.
+    pub interval: String,
+    /// Interval of the venue heartbeats asked with `public/set_heartbeat`, 10 at least.
+    pub heartbeat_interval_secs: u64,
+    /// Silence after which the connection is taken for dead and replaced.
+    pub idle_timeout_secs: u64,
+    /// API key for the private channels, which are left alone while it is empty.
+    pub client_id: String,
+    pub client_secret: Secret,
+    pub auth: AuthMethod,
+    /// Currency of the `user.portfolio` channel.
+    pub currency: String,
+}
+
+impl Default for DeribitConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            url: "wss://www.deribit.com/ws/api/v2/".to_string(),
+            instrument: "BTC-PERPETUAL".to_string(),
+            interval: "100ms".to_string(),
+            heartbeat_interval_secs: 10,
+            idle_timeout_secs: 30,
+            client_id: String::new(),
+            client_secret: Secret::default(),
+            auth: AuthMethod::ClientSignature,
+            currency: "BTC".to_string(),
+        }
+    }
+}
+
+impl DeribitConfig {
+    pub fn channel(&self) -> String {
+        format!("book.{}.{}", self.instrument, self.interval)
+    }
+
+    /// Whether the session authenticates and follows the private channels.
+    pub fn is_authenticated(&self) -> bool {
+        !self.client_id.is_empty()
+    }
+
+    /// Own orders on the instrument as they change, and the account of `currency`.
+    pub fn private_channels(&self) -> Vec<String> {
+        vec![format!("user.orders.{}.raw", self.instrument), format!("user.portfolio.{}", self.currency)]
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct OKXConfig {
+    pub enabled: bool,
+    pub url: String,
+    pub instrument: String,
+    pub channel: String,
+    /// Silence after which a `ping` is sent, the venue drops connections quiet for 30 seconds.
+    pub ping_interval_secs: u64,
+    /// Silence after which the connection is taken for dead and replaced.
+    pub idle_timeout_secs: u64,
+}
+
+impl Default for OKXConfig {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            url: "wss://ws.okx.com:8443/ws/v5/public".to_string(),
+            instrument: "BTC-USD-SWAP".to_string(),
+            channel: "books".to_string(),
+            ping_interval_secs: 20,
+            idle_timeout_secs: 30,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct VenuesConfig {
+    pub deribit: DeribitConfig,
+    pub okx: OKXConfig,
+}
+
+impl VenuesConfig {
+    pub fn enabled_names(&self) -> Vec<&'static str> {
+        let mut names = vec![];
+        if self.deribit.enabled {
+            names.push("deribit");
+        }
+        if self.okx.enabled {
+            names.push("okx");
+        }
+        names
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SinksConfig {
+    /// Print the book to stdout after every operation.
+    pub stdout: bool,
+    /// Append every raw frame to this capture file.
+    pub capture: Option<PathBuf>,
+}
+
+impl Default for SinksConfig {
+    fn default() -> Self {
+        Self {
+            stdout: true,
+            capture: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PersistenceConfig {
+    /// File the book is saved to and warm started from, nothing is saved when not set.
+    pub path: Option<PathBuf>,
+    /// Seconds between saves, the book is also saved at shutdown.
+    pub interval_secs: u64,
+}
+
+impl Default for PersistenceConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            interval_secs: 10,
+        }
+    }
+}
+
+/// Fees of one venue in basis points of the notional, negative for a rebate.
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FeeSchedule {
+    pub maker_bps: f64,
+    pub taker_bps: f64,
+}
+
+impl FeeSchedule {
+    fn is_valid(&self) -> bool {
+        [self.maker_bps, self.taker_bps].iter().all(|x| x.is_finite() && x.abs() < 10_000.0)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FeesConfig {
+    pub deribit: FeeSchedule,
+    pub okx: FeeSchedule,
+}
+
+impl Default for FeesConfig {
+    /// Base tier fees of the perpetual swaps.
+    fn default() -> Self {
+        Self {
+            deribit: FeeSchedule { maker_bps: 0.0, taker_bps: 5.0 },
+            okx: FeeSchedule { maker_bps: 2.0, taker_bps: 5.0 },
+        }
+    }
+}
+
+impl FeesConfig {
+    pub fn schedule(&self, source: Source) -> FeeSchedule {
+        match source {
+            Source::Deribit => self.deribit,
+            Source::OKX => self.okx,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ArbConfig {
+    /// Print cross-venue arbitrage signals.
+    pub enabled: bool,
+    /// How long a crossing must last before it is reported.
+    pub debounce_ms: u64,
+}
+
+impl Default for ArbConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            debounce_ms: 500,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AnalyticsConfig {
+    /// Print the microstructure signals after every operation.
+    pub enabled: bool,
+    /// Levels per side the imbalance, weighted mid and slope look at.
+    pub top_n: usize,
+    /// Distance from the mid the depth is measured within.
+    pub depth_bps: f64,
+    /// Time the rolling signals are averaged over.
+    pub window_ms: u64,
+}
+
+impl Default for AnalyticsConfig {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            top_n: 5,
+            depth_bps: 10.0,
+            window_ms: 1_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    /// Address serving the Prometheus `/metrics` endpoint, disabled when not set.
+    pub listen: Option<SocketAddr>,
+}
+
+/// What the channel between the connectors and the book does once it is full.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum QueuePolicy {
+    /// Connectors wait for the book to catch up.
+    Block,
+    /// Evict the oldest operation and resync its source.
+    DropOldest,
+    /// Merge operations of the same source while they wait, whatever the capacity.
+    Conflate,
+}
+
+impl FromStr for QueuePolicy {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "block" => Ok(QueuePolicy::Block),
+            "drop-oldest" => Ok(QueuePolicy::DropOldest),
+            "conflate" => Ok(QueuePolicy::Conflate),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChannelConfig {
+    /// Operations waiting to be applied before the policy kicks in.
+    pub capacity: usize,
+    pub policy: QueuePolicy,
+}
+
+impl Default for ChannelConfig {
+    fn default() -> Self {
+        Self {
+            capacity: 1024,
+            policy: QueuePolicy::Block,
+        }
+    }
+}
+
+/// What the supervisor does when a connector stops on an error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RestartPolicy {
+    /// Leave the venue down, the book carries on with the others.
+    Never,
+    /// Start the connector again after a backoff.
+    OnFailure,
+}
+
+impl FromStr for RestartPolicy {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "never" => Ok(RestartPolicy::Never),
+            "on-failure" => Ok(RestartPolicy::OnFailure),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SupervisorConfig {
+    pub restart: RestartPolicy,
+    /// Restarts in a row before giving up on a venue, unlimited when not set.
+    pub max_restarts: Option<u32>,
+    /// Delay before the first restart, doubled on every failure in a row.
+    pub backoff_initial_ms: u64,
+    /// Longest delay between restarts, a connector that ran this long counts as healthy again.
+    pub backoff_max_ms: u64,
+}
+
+impl Default for SupervisorConfig {
+    fn default() -> Self {
+        Self {
+            restart: RestartPolicy::OnFailure,
+            max_restarts: None,
+            backoff_initial_ms: 1_000,
+            backoff_max_ms: 30_000,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TraceExporter {
+    OtlpGrpc,
+    OtlpHttp,
+    Jaeger,
+    Stdout,
+    None,
+}
+
+impl FromStr for TraceExporter {
+    type Err = ();
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "otlp-grpc" => Ok(TraceExporter::OtlpGrpc),
+            "otlp-http" => Ok(TraceExporter::OtlpHttp),
+            "jaeger" => Ok(TraceExporter::Jaeger),
+            "stdout" => Ok(TraceExporter::Stdout),
+            "none" => Ok(TraceExporter::None),
+            _ => Err(()),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TelemetryConfig {
+    pub service_name: String,
+    pub exporter: TraceExporter,
+    /// Collector or agent endpoint, the exporter default (or its `OTEL_*` variables) when not set.
+    pub endpoint: Option<String>,
+    /// Fraction of traces kept, every message and book operation is its own trace.
+    pub sampling_ratio: f64,
+    /// `service.instance.id` resource attribute, the host name when not set.
+    pub instance_id: Option<String>,
+    /// Trace every websocket message in the connectors.
+    pub message_spans: bool,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            service_name: "top-book".to_string(),
+            exporter: TraceExporter::Jaeger,
+            endpoint: None,
+            sampling_ratio: 1.0,
+            instance_id: None,
+            message_spans: false,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub depth: usize,
+    pub venues: VenuesConfig,
+    pub channel: ChannelConfig,
+    pub supervisor: SupervisorConfig,
+    pub sinks: SinksConfig,
+    pub persistence: PersistenceConfig,
+    pub fees: FeesConfig,
+    pub arb: ArbConfig,
+    pub analytics: AnalyticsConfig,
+    pub metrics: MetricsConfig,
+    pub telemetry: TelemetryConfig,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            depth: 200,
+            venues: VenuesConfig::default(),
+            channel: ChannelConfig::default(),
+            supervisor: SupervisorConfig::default(),
+            sinks: SinksConfig::default(),
+            persistence: PersistenceConfig::default(),
+            fees: FeesConfig::default(),
+            arb: ArbConfig::default(),
+            analytics: AnalyticsConfig::default(),
+            metrics: MetricsConfig::default(),
+            telemetry: TelemetryConfig::default(),
+        }
+    }
+}
+
+fn parse_env<T: std::str::FromStr>(name: &str, value: &str) -> Result<T, ConfigError> {
+    value.parse::<T>().map_err(|_| ConfigError::InvalidEnv {
+        name: name.to_string(),
+        value: value.to_string(),
+    })
+}
+
+impl Config {
+    /// Loads the configuration from `path`, or the defaults when there is no file, then applies the
+    /// `TOP_BOOK_*` environment overrides and validates the result.
+    pub fn load(path: Option<&Path>) -> Result<Self, ConfigError> {
+        let mut config = match path {
+            Some(path) => {
+                let content = std::fs::read_to_string(path)
+                    .map_err(|err| ConfigError::Read(path.to_path_buf(), err))?;
+                Config::parse(&content)?
+            }
+            None => Config::default(),
+        };
+        config.apply_env(std::env::vars())?;
+        config.validate()?;
+        Ok(config)
+    }
+
+    pub fn parse(content: &str) -> Result<Self, ConfigError> {
+        toml::from_str(content).map_err(ConfigError::Parse)
+    }
+
+    pub fn apply_env(&mut self, vars: impl Iterator<Item=(String, String)>) -> Result<(), ConfigError> {
+        for (name, value) in vars {
+            let key = match name.strip_prefix(ENV_PREFIX) {
+                Some(key) => key,
+                None => continue,
+            };
+            match key {
+                "DEPTH" => self.depth = parse_env(&name, &value)?,
+                "DERIBIT_ENABLED" => self.venues.deribit.enabled = parse_env(&name, &value)?,
+                "DERIBIT_URL" => self.venues.deribit.url = value,
+                "DERIBIT_INSTRUMENT" => self.venues.deribit.instrument = value,
+                "DERIBIT_INTERVAL" => self.venues.deribit.interval = value,
+                "DERIBIT_HEARTBEAT_INTERVAL_SECS" => self.venues.deribit.heartbeat_interval_secs = parse_env(&name, &value)?,
+                "DERIBIT_IDLE_TIMEOUT_SECS" => self.venues.deribit.idle_timeout_secs = parse_env(&name, &value)?,
+                "DERIBIT_CLIENT_ID" => self.venues.deribit.client_id = value,
+                "DERIBIT_CLIENT_SECRET" => self.venues.deribit.client_secret = Secret::new(value),
+                "DERIBIT_AUTH" => self.venues.deribit.auth = parse_env(&name, &value)?,
+                "DERIBIT_CURRENCY" => self.venues.deribit.currency = value,
+                "OKX_ENABLED" => self.venues.okx.enabled = parse_env(&name, &value)?,
+                "OKX_URL" => self.venues.okx.url = value,
+                "OKX_INSTRUMENT" => self.venues.okx.instrument = value,
+                "OKX_CHANNEL" => self.venues.okx.channel = value,
+                "OKX_PING_INTERVAL_SECS" => self.venues.okx.ping_interval_secs = parse_env(&name, &value)?,
+                "OKX_IDLE_TIMEOUT_SECS" => self.venues.okx.idle_timeout_secs = parse_env(&name, &value)?,
+                "CHANNEL_CAPACITY" => self.channel.capacity = parse_env(&name, &value)?,
+                "CHANNEL_POLICY" => self.channel.policy = parse_env(&name, &value)?,
+                "SUPERVISOR_RESTART" => self.supervisor.restart = parse_env(&name, &value)?,
+                "SUPERVISOR_MAX_RESTARTS" => self.supervisor.max_restarts = Some(parse_env(&name, &value)?),
+                "SUPERVISOR_BACKOFF_INITIAL_MS" => self.supervisor.backoff_initial_ms = parse_env(&name, &value)?,
+                "SUPERVISOR_BACKOFF_MAX_MS" => self.supervisor.backoff_max_ms = parse_env(&name, &value)?,
+                "SINKS_STDOUT" => self.sinks.stdout = parse_env(&name, &value)?,
+                "SINKS_CAPTURE" => self.sinks.capture = Some(PathBuf::from(value)),
+                "PERSISTENCE_PATH" => self.persistence.path = Some(PathBuf::from(value)),
+                "PERSISTENCE_INTERVAL_SECS" => self.persistence.interval_secs = parse_env(&name, &value)?,
+                "FEES_DERIBIT_MAKER_BPS" => self.fees.deribit.maker_bps = parse_env(&name, &value)?,
+                "FEES_DERIBIT_TAKER_BPS" => self.fees.deribit.taker_bps = parse_env(&name, &value)?,
+                "FEES_OKX_MAKER_BPS" => self.fees.okx.maker_bps = parse_env(&name, &value)?,
+                "FEES_OKX_TAKER_BPS" => self.fees.okx.taker_bps = parse_env(&name, &value)?,
+                "ARB_ENABLED" => self.arb.enabled = parse_env(&name, &value)?,
+                "ARB_DEBOUNCE_MS" => self.arb.debounce_ms = parse_env(&name, &value)?,
+                "ANALYTICS_ENABLED" => self.analytics.enabled = parse_env(&name, &value)?,
+                "ANALYTICS_TOP_N" => self.analytics.top_n = parse_env(&name, &value)?,
+                "ANALYTICS_DEPTH_BPS" => self.analytics.depth_bps = parse_env(&name, &value)?,
+                "ANALYTICS_WINDOW_MS" => self.analytics.window_ms = parse_env(&name, &value)?,
+                "METRICS_LISTEN" => self.metrics.listen = Some(parse_env(&name, &value)?),
+                "TELEMETRY_SERVICE_NAME" => self.telemetry.service_name = value,
+                "TELEMETRY_EXPORTER" => self.telemetry.exporter = parse_env(&name, &value)?,
+                "TELEMETRY_ENDPOINT" => self.telemetry.endpoint = Some(value),
+                "TELEMETRY_SAMPLING_RATIO" => self.telemetry.sampling_ratio = parse_env(&name, &value)?,
+                "TELEMETRY_INSTANCE_ID" => self.telemetry.instance_id = Some(value),
+                "TELEMETRY_MESSAGE_SPANS" => self.telemetry.message_spans = parse_env(&name, &value)?,
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if self.depth == 0 {
+            return Err(ConfigError::ZeroDepth);
+        }
+        if !self.venues.deribit.enabled && !self.venues.okx.enabled {
+            return Err(ConfigError::NoVenueEnabled);
+        }
+        if self.venues.deribit.enabled {
+            Url::parse(&self.venues.deribit.url).map_err(|err| ConfigError::InvalidUrl("venues.deribit", err))?;
+            if self.venues.deribit.instrument.is_empty() {
+                return Err(ConfigError::EmptyInstrument("venues.deribit"));
+            }
+            let deribit = &self.venues.deribit;
+            if deribit.heartbeat_interval_secs < 10 || deribit.heartbeat_interval_secs >= deribit.idle_timeout_secs {
+                return Err(ConfigError::InvalidKeepalive("venues.deribit"));
+            }
+            if deribit.client_id.is_empty() != deribit.client_secret.is_empty() {
+                return Err(ConfigError::IncompleteCredentials("venues.deribit"));
+            }
+        }
+        if self.venues.okx.enabled {
+            Url::parse(&self.venues.okx.url).map_err(|err| ConfigError::InvalidUrl("venues.okx", err))?;
+            if self.venues.okx.instrument.is_empty() {
+                return Err(ConfigError::EmptyInstrument("venues.okx"));
+            }
+            let okx = &self.venues.okx;
+            if okx.ping_interval_secs == 0 || okx.ping_interval_secs >= okx.idle_timeout_secs.min(30) {
+                return Err(ConfigError::InvalidKeepalive("venues.okx"));
+            }
+        }
+        if self.channel.capacity == 0 {
+            return Err(ConfigError::ZeroChannelCapacity);
+        }
+        if self.supervisor.backoff_initial_ms == 0
+            || self.supervisor.backoff_initial_ms > self.supervisor.backoff_max_ms {
+            return Err(ConfigError::InvalidBackoff);
+        }
+        if self.persistence.interval_secs == 0 {
+            return Err(ConfigError::ZeroPersistenceInterval);
+        }
+        if !self.fees.deribit.is_valid() {
+            return Err(ConfigError::InvalidFee("fees.deribit"));
+        }
+        if !self.fees.okx.is_valid() {
+            return Err(ConfigError::InvalidFee("fees.okx"));
+        }
+        let analytics = &self.analytics;
+        if analytics.top_n == 0 || analytics.window_ms == 0 || !analytics.depth_bps.is_finite() ||
+            analytics.depth_bps < 0.0 {
+            return Err(ConfigError::InvalidAnalytics);
+        }
+        if self.telemetry.service_name.is_empty() {
+            return Err(ConfigError::EmptyServiceName);
+        }
+        if !(0.0..=1.0).contains(&self.telemetry.sampling_ratio) {
+            return Err(ConfigError::InvalidSamplingRatio(self.telemetry.sampling_ratio));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::config::{
+        AuthMethod,
+        Config,
+        ConfigError,
+        FeeSchedule,
+        QueuePolicy,
+        TraceExporter,
+    };
+
+    #[test]
+    fn should_parse_a_partial_config() {
+        // Given
+        let content = r#"
+depth = 50
+
+[venues.deribit]
+instrument = "ETH-PERPETUAL"
+
+[venues.okx]
+enabled = false
+"#;
+
+        // When
+        let config = Config::parse(content).unwrap();
+
+        // Then
+        assert_eq!(50, config.depth);
+        assert_eq!("book.ETH-PERPETUAL.100ms", config.venues.deribit.channel());
+        assert!(!config.venues.okx.enabled);
+        assert!(config.sinks.stdout);
+        assert_eq!("top-book", config.telemetry.service_name);
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn should_parse_the_example_config() {
+        // Given
+        let content = include_str!("../top-book.example.toml");
+
+        // When
+        let config = Config::parse(content).unwrap();
+
+        // Then
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn should_reject_unknown_fields() {
+        // Given
+        let content = "[venues.binance]\nenabled = true\n";
+
+        // When
+        let config = Config::parse(content);
+
+        // Then
+        assert!(matches!(config, Err(ConfigError::Parse(_))));
+    }
+
+    #[test]
+    fn should_apply_env_overrides() {
+        // Given
+        let mut config = Config::default();
+        let vars = vec![
+            ("TOP_BOOK_DEPTH".to_string(), "10".to_string()),
+            ("TOP_BOOK_OKX_INSTRUMENT".to_string(), "ETH-USD-SWAP".to_string()),
+            ("HOME".to_string(), "/root".to_string()),
+        ];
+
+        // When
+        config.apply_env(vars.into_iter()).unwrap();
+
+        // Then
+        assert_eq!(10, config.depth);
+        assert_eq!("ETH-USD-SWAP", config.venues.okx.instrument);
+    }
+
+    #[test]
+    fn should_report_an_invalid_env_override() {
+        // Given
+        let mut config = Config::default();
+        let vars = vec![("TOP_BOOK_DEPTH".to_string(), "deep".to_string())];
+
+        // When
+        let resp = config.apply_env(vars.into_iter());
+
+        // Then
+        assert_eq!(
+            "invalid value \"deep\" for TOP_BOOK_DEPTH",
+            resp.err().unwrap().to_string(),
+        );
+    }
+
+    #[test]
+    fn should_parse_the_trace_exporter() {
+        // Given
+        let content = "[telemetry]\nexporter = \"otlp-grpc\"\nsampling_ratio = 0.01\n";
+        let vars = vec![("TOP_BOOK_TELEMETRY_EXPORTER".to_string(), "none".to_string())];
+
+        // When
+        let mut config = Config::parse(content).unwrap();
+        let parsed = config.telemetry.exporter;
+        config.apply_env(vars.into_iter()).unwrap();
+
+        // Then
+        assert_eq!(TraceExporter::OtlpGrpc, parsed);
+        assert_eq!(TraceExporter::None, config.telemetry.exporter);
+        assert_eq!(0.01, config.telemetry.sampling_ratio);
+    }
+
+    #[test]
+    fn should_reject_an_invalid_sampling_ratio() {
+        // Given
+        let mut config = Config::default();
+        config.telemetry.sampling_ratio = 1.5;
+
+        // When
+        let resp = config.validate();
+
+        // Then
+        assert!(matches!(resp, Err(ConfigError::InvalidSamplingRatio(_))));
+    }
+
+    #[test]
+    fn should_require_one_venue() {
+        // Given
+        let mut config = Config::default();
+        config.venues.deribit.enabled = false;
+        config.venues.okx.enabled = false;
+
+        // When
+        let resp = config.validate();
+
+        // Then
+        assert!(matches!(resp, Err(ConfigError::NoVenueEnabled)));
+    }
+
+    #[test]
+    fn should_parse_the_channel_policy() {
+        // Given
+        let content = "[channel]\ncapacity = 16\npolicy = \"drop-oldest\"\n";
+        let vars = vec![("TOP_BOOK_CHANNEL_POLICY".to_string(), "conflate".to_string())];
+
+        // When
+        let mut config = Config::parse(content).unwrap();
+        let parsed = config.channel.policy;
+        config.apply_env(vars.into_iter()).unwrap();
+        config.channel.capacity = 0;
+
+        // Then
+        assert_eq!(QueuePolicy::DropOldest, parsed);
+        assert_eq!(QueuePolicy::Conflate, config.channel.policy);
+        assert!(matches!(config.validate(), Err(ConfigError::ZeroChannelCapacity)));
+    }
+
+    #[test]
+    fn should_parse_the_fee_schedules() {
+        // Given
+        let content = "[fees.okx]\nmaker_bps = -0.5\ntaker_bps = 3.0\n";
+        let vars = vec![("TOP_BOOK_FEES_DERIBIT_TAKER_BPS".to_string(), "20000".to_string())];
+
+        // When
+        let mut config = Config::parse(content).unwrap();
+        let okx = config.fees.okx;
+        config.apply_env(vars.into_iter()).unwrap();
+
+        // Then
+        assert_eq!(FeeSchedule { maker_bps: -0.5, taker_bps: 3.0 }, okx);
+        assert_eq!(0.0, config.fees.deribit.maker_bps);
+        assert!(matches!(config.validate(), Err(ConfigError::InvalidFee("fees.deribit"))));
+    }
+
+    #[test]
+    fn should_accept_a_zero_analytics_depth_but_not_a_negative_one() {
+        // Given
+        let mut config = Config::default();
+        config.analytics.depth_bps = 0.0;
+
+        // When
+        let resp = config.validate();
+
+        // Then
+        assert!(resp.is_ok());
+        config.analytics.depth_bps = -1.0;
+        let err = config.validate().err().unwrap();
+        assert!(matches!(err, ConfigError::InvalidAnalytics));
+        assert!(err.to_string().contains("depth_bps finite and not negative"));
+    }
+
+    #[test]
+    fn should_check_the_keepalive_against_the_venue_limits() {
+        // Given
+        let mut config = Config::default();
+        let vars = vec![
+            ("TOP_BOOK_DERIBIT_HEARTBEAT_INTERVAL_SECS".to_string(), "15".to_string()),
+            ("TOP_BOOK_OKX_PING_INTERVAL_SECS".to_string(), "30".to_string()),
+        ];
+
+        // When
+        config.apply_env(vars.into_iter()).unwrap();
+        let resp = config.validate();
+
+        // Then
+        assert_eq!(15, config.venues.deribit.heartbeat_interval_secs);
+        assert!(matches!(resp, Err(ConfigError::InvalidKeepalive("venues.okx"))));
+        config.venues.okx.ping_interval_secs = 25;
+        config.venues.deribit.heartbeat_interval_secs = 5;
+        assert!(matches!(config.validate(), Err(ConfigError::InvalidKeepalive("venues.deribit"))));
+    }
+
+    #[test]
+    fn should_take_the_credentials_from_the_env_and_keep_them_out_of_logs() {
+        // Given
+        let content = "[venues.deribit]\nclient_id = \"abc\"\nauth = \"client-credentials\"\n";
+        let vars = vec![("TOP_BOOK_DERIBIT_CLIENT_SECRET".to_string(), "s3cr3t".to_string())];
+
+        // When
+        let mut config = Config::parse(content).unwrap();
+        let incomplete = config.validate();
+        config.apply_env(vars.into_iter()).unwrap();
+
+        // Then
+        assert!(matches!(incomplete, Err(ConfigError::IncompleteCredentials("venues.deribit"))));
+        assert!(config.validate().is_ok());
+        assert!(config.venues.deribit.is_authenticated());
+        assert_eq!(AuthMethod::ClientCredentials, config.venues.deribit.auth);
+        assert_eq!("s3cr3t", config.venues.deribit.client_secret.expose());
+        assert!(!format!("{:?}", config).contains("s3cr3t"));
+        assert_eq!(
+            vec!["user.orders.BTC-PERPETUAL.raw", "user.portfolio.BTC"],
+            config.venues.deribit.private_channels(),
+        );
+    }
+}
@@ -0,0 +1,283 @@
+use crate::orderbook::Level;
+use futures_util::{
+    Sink,
+    SinkExt,
+    Stream,
+    StreamExt,
+};
+use serde::de::{
+    Deserialize,
+    DeserializeSeed,
+    Deserializer,
+    IgnoredAny,
+    SeqAccess,
+    Visitor,
+};
+use serde_json::value::RawValue;
+use std::{
+    fmt,
+    marker::PhantomData,
+    time::Duration,
+};
+use tokio::{
+    io::AsyncWriteExt,
+    sync::watch,
+    time::Instant,
+};
+use tokio_tungstenite::tungstenite::{
+    self,
+    Message,
+};
+
+/// How long a connector waits for the venue to answer its close frame.
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// What a connector does after failing to handle a message.
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum ErrorPolicy {
+    /// Drop the message and carry on.
+    Skip,
+    /// The book may have missed an update, subscribe again to get a fresh snapshot.
+    Resync,
+    /// The session is broken, open a new connection.
+    Reconnect,
+    /// Nobody can use what the connector produces, stop it.
+    Stop,
+}
+
+impl ErrorPolicy {
+    pub fn label(&self) -> &'static str {
+        match self {
+            ErrorPolicy::Skip => "skip",
+            ErrorPolicy::Resync => "resync",
+            ErrorPolicy::Reconnect => "reconnect",
+            ErrorPolicy::Stop => "stop",
+        }
+    }
+}
+
+/// An error of a connector, which says what to do about it.
+pub trait ConnectorError {
+    fn policy(&self) -> ErrorPolicy;
+}
+
+/// Writes a log line to stdout, a failing stdout is not worth stopping a connector for.
+pub async fn log(message: String) {
+    let _ = tokio::io::stdout().write_all(message.as_bytes()).await;
+}
+
+/// Fires [`Shutdown`] for every task holding a copy.
+pub struct ShutdownTrigger {
+    sender: watch::Sender<bool>,
+}
+
+impl ShutdownTrigger {
+    pub fn trigger(&self) {
+        self.sender.send_replace(true);
+    }
+}
+
+/// Tells long running tasks to wrap up, cheap to clone.
+#[derive(Clone)]
+pub struct Shutdown {
+    receiver: watch::Receiver<bool>,
+}
+
+impl Shutdown {
+    pub fn is_triggered(&self) -> bool {
+        *self.receiver.borrow()
+    }
+
+    /// Resolves once shutdown is triggered, never when the trigger is dropped without firing.
+    pub async fn wait(&mut self) {
+        while !self.is_triggered() {
+            if self.receiver.changed().await.is_err() {
+                std::future::pending::<()>().await;
+            }
+        }
+    }
+}
+
+pub fn shutdown() -> (ShutdownTrigger, Shutdown) {
+    let (sender, receiver) = watch::channel(false);
+    (ShutdownTrigger { sender }, Shutdown { receiver })
+}
+
+/// Leaves a websocket session cleanly: sends `requests` (the unsubscribe), then a close frame, and
+/// waits a little for the venue to close its side.
+pub async fn close_session<W, R>(
+    write: &mut W, read: &mut R, requests: Vec<Message>,
+) -> Result<(), tungstenite::Error>
+    where
+        W: Sink<Message, Error=tungstenite::Error> + Unpin,
+        R: Stream<Item=Result<Message, tungstenite::Error>> + Unpin,
+{
+    for request in requests {
+        write.send(request).await?;
+    }
+    write.send(Message::Close(None)).await?;
+
+    let closed = async {
+        while let Some(message) = read.next().await {
+            if let Ok(Message::Close(_)) | Err(_) = message {
+                break;
+            }
+        }
+    };
+    // A venue that never answers is not a reason to hang the shutdown.
+    let _ = tokio::time::timeout(CLOSE_TIMEOUT, closed).await;
+    Ok(())
+}
+
+/// What a quiet session needs.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Keepalive {
+    /// Nothing came for a while, ask the venue for a sign of life.
+    Ping,
+    /// Nothing came for too long, the connection is dead even if the socket looks open.
+    Dead,
+}
+
+/// Tracks how long a session has not heard from its venue.
+pub struct Liveness {
+    last_received: Instant,
+    ping_after: Option<Duration>,
+    dead_after: Duration,
+    pinged: bool,
+}
+
+impl Liveness {
+    /// Without `ping_after` the session counts on heartbeats the venue sends on its own.
+    pub fn new(ping_after: Option<Duration>, dead_after: Duration) -> Self {
+        Self { last_received: Instant::now(), ping_after, dead_after, pinged: false }
+    }
+
+    /// Any frame counts, pongs included.
+    pub fn received(&mut self) {
+        self.last_received = Instant::now();
+        self.pinged = false;
+    }
+
+    /// Resolves once the session has been quiet long enough to ping the venue, once per silence,
+    /// or to give up on it.
+    pub async fn wait(&mut self) -> Keepalive {
+        match self.ping_after {
+            Some(ping_after) if !self.pinged && ping_after < self.dead_after => {
+                tokio::time::sleep_until(self.last_received + ping_after).await;
+                self.pinged = true;
+                Keepalive::Ping
+            }
+            _ => {
+                tokio::time::sleep_until(self.last_received + self.dead_after).await;
+                Keepalive::Dead
+            }
+        }
+    }
+}
+
+/// Reads a JSON array of levels straight into a buffer, `T` borrows one level from the message.
+struct LevelsSeed<'b, T, E, F> {
+    buffer: &'b mut Vec<Level>,
+    level: F,
+    marker: PhantomData<fn(T) -> E>,
+}
+
+impl<'de, T, E, F> DeserializeSeed<'de> for LevelsSeed<'_, T, E, F>
+    where
+        T: Deserialize<'de>,
+        F: Fn(T) -> Result<Level, E>,
+{
+    type Value = Result<(), E>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_seq(self)
+    }
+}
+
+impl<'de, T, E, F> Visitor<'de> for LevelsSeed<'_, T, E, F>
+    where
+        T: Deserialize<'de>,
+        F: Fn(T) -> Result<Level, E>,
+{
+    type Value = Result<(), E>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("an array of levels")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        while let Some(level) = seq.next_element::<T>()? {
+            match (self.level)(level) {
+                Ok(level) => self.buffer.push(level),
+                Err(err) => {
+                    // The array still has to be read to its end.
+                    while seq.next_element::<IgnoredAny>()?.is_some() {}
+                    return Ok(Err(err));
+                }
+            }
+        }
+        Ok(Ok(()))
+    }
+}
+
+/// Parses the levels of `raw` into `buffer`, `level` checks each one as `T` read it.
+///
+/// The buffer is cleared first but keeps its capacity, so a connector reusing it allocates nothing
+/// per level. The first level `level` rejects is returned, malformed JSON goes to `parse_err`.
+pub fn parse_levels<'a, T, E, F>(
+    raw: &'a RawValue, buffer: &mut Vec<Level>, level: F, parse_err: fn(serde_json::Error) -> E,
+) -> Result<(), E>
+    where
+        T: Deserialize<'a>,
+        F: Fn(T) -> Result<Level, E>,
+{
+    buffer.clear();
+    let mut deserializer = serde_json::Deserializer::from_str(raw.get());
+    LevelsSeed { buffer, level, marker: PhantomData }
+        .deserialize(&mut deserializer)
+        .map_err(parse_err)?
+}
+
+#[cfg(test)]
+mod test {
+    use crate::connector::{
+        shutdown,
+        Keepalive,
+        Liveness,
+    };
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn should_wake_every_copy_on_shutdown() {
+        // Given
+        let (trigger, shutdown) = shutdown();
+        let mut first = shutdown.clone();
+        let mut second = shutdown;
+
+        // When
+        let waiting = tokio::spawn(async move { first.wait().await });
+        trigger.trigger();
+
+        // Then
+        waiting.await.unwrap();
+        second.wait().await;
+        assert!(second.is_triggered());
+    }
+
+    #[tokio::test]
+    async fn should_ping_once_then_give_up_on_a_quiet_session() {
+        // Given
+        let mut liveness = Liveness::new(Some(Duration::from_millis(20)), Duration::from_millis(50));
+
+        // When
+        let first = liveness.wait().await;
+        let second = liveness.wait().await;
+        liveness.received();
+        let after_data = liveness.wait().await;
+
+        // Then
+        assert_eq!(Keepalive::Ping, first);
+        assert_eq!(Keepalive::Dead, second);
+        assert_eq!(Keepalive::Ping, after_data);
+    }
+}
@@ -0,0 +1,174 @@
+use crate::{
+    config::{
+        FeeSchedule,
+        FeesConfig,
+    },
+    orderbook::{
+        Orderbook,
+        Side,
+        Source,
+    },
+};
+/// Whether an order rests on the book or takes from it, which decides the fee it pays.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Liquidity {
+    Maker,
+    Taker,
+}
+
+impl FeeSchedule {
+    /// Fee as a fraction of the notional.
+    pub fn rate(&self, liquidity: Liquidity) -> f64 {
+        match liquidity {
+            Liquidity::Maker => self.maker_bps / 10_000.0,
+            Liquidity::Taker => self.taker_bps / 10_000.0,
+        }
+    }
+}
+
+/// Price actually paid for an ask, or received for a bid, once the fee is counted.
+pub fn effective_price(side: Side, price: f64, rate: f64) -> f64 {
+    match side {
+        Side::Ask => price * (1.0 + rate),
+        Side::Bid => price * (1.0 - rate),
+    }
+}
+
+/// The quote of one venue at a level of the merged book, priced after its fee.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EffectiveLevel {
+    /// Quoted price of the level.
+    pub price: f64,
+    /// Price after the fee of `source`.
+    pub effective_price: f64,
+    pub source: Source,
+    /// What `source` quotes at the level.
+    pub size: f64,
+}
+
+/// Fee-adjusted view of one side of the book, one entry per venue quoting a level, best effective
+/// price first.
+///
+/// Asks are raised and bids lowered by the fee of each venue, which can reorder quotes of
+/// different venues that are close in quoted price, or even at the same one.
+pub fn fee_adjusted(orderbook: &Orderbook, side: Side, fees: &FeesConfig, liquidity: Liquidity) -> Vec<EffectiveLevel> {
+    let mut resp: Vec<EffectiveLevel> = orderbook.levels(side).iter()
+        .flat_map(|level| level.source_size.iter().map(move |(source, size)| (level.price, *source, *size)))
+        .map(|(price, source, size)| EffectiveLevel {
+            price,
+            effective_price: effective_price(side, price, fees.schedule(source).rate(liquidity)),
+            source,
+            size,
+        })
+        .collect();
+    // Stable, so quotes at the same effective price keep their quoted order, then the source one.
+    resp.sort_by(|a, b| {
+        let by_price = match side {
+            Side::Ask => a.effective_price.total_cmp(&b.effective_price),
+            Side::Bid => b.effective_price.total_cmp(&a.effective_price),
+        };
+        by_price.then(a.source.cmp(&b.source))
+    });
+    resp
+}
+
+/// Whether `price` beats `than` on `side`: a lower ask or a higher bid.
+pub(crate) fn is_better(side: Side, price: f64, than: f64) -> bool {
+    match side {
+        Side::Ask => price < than,
+        Side::Bid => price > than,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        config::{
+            FeeSchedule,
+            FeesConfig,
+        },
+        fees::{
+            fee_adjusted,
+            Liquidity,
+        },
+        orderbook::{
+            Level,
+            Operation,
+            Orderbook,
+            Side,
+            Source,
+        },
+    };
+
+    fn fees() -> FeesConfig {
+        FeesConfig {
+            deribit: FeeSchedule { maker_bps: 0.0, taker_bps: 10.0 },
+            okx: FeeSchedule { maker_bps: -1.0, taker_bps: 2.0 },
+        }
+    }
+
+    fn orderbook() -> Orderbook {
+        let mut orderbook = Orderbook::new(20);
+        orderbook.process(Operation::Snapshot {
+            asks: vec![Level { price: 10_000.0, size: 1.0 }],
+            bids: vec![Level { price: 9_990.0, size: 1.0 }],
+            source: Source::Deribit,
+            sequence: None,
+        });
+        orderbook.process(Operation::Snapshot {
+            asks: vec![Level { price: 10_000.0, size: 1.0 }, Level { price: 10_005.0, size: 2.0 }],
+            bids: vec![Level { price: 9_985.0, size: 1.0 }],
+            source: Source::OKX,
+            sequence: None,
+        });
+        orderbook
+    }
+
+    #[test]
+    fn should_raise_asks_by_the_taker_fee_of_each_venue() {
+        // Given
+        let orderbook = orderbook();
+
+        // When
+        let asks = fee_adjusted(&orderbook, Side::Ask, &fees(), Liquidity::Taker);
+
+        // Then
+        // Both venues quote 10000, Deribit's costs 10010 after its fee, worse than the OKX 10005.
+        let quotes: Vec<(f64, Source, f64)> = asks.iter().map(|x| (x.price, x.source, x.size)).collect();
+        assert_eq!(vec![
+            (10_000.0, Source::OKX, 1.0),
+            (10_005.0, Source::OKX, 2.0),
+            (10_000.0, Source::Deribit, 1.0),
+        ], quotes);
+        for (ask, expected) in asks.iter().zip([10_002.0, 10_007.001, 10_010.0]) {
+            assert!((ask.effective_price - expected).abs() < 1e-6);
+        }
+    }
+
+    #[test]
+    fn should_reorder_bids_once_lowered_by_the_fee() {
+        // Given
+        let orderbook = orderbook();
+
+        // When
+        let bids = fee_adjusted(&orderbook, Side::Bid, &fees(), Liquidity::Taker);
+
+        // Then
+        // Deribit quotes the better bid but its fee makes OKX pay more.
+        let prices: Vec<(f64, Source)> = bids.iter().map(|x| (x.price, x.source)).collect();
+        assert_eq!(vec![(9_985.0, Source::OKX), (9_990.0, Source::Deribit)], prices);
+    }
+
+    #[test]
+    fn should_apply_maker_rebates() {
+        // Given
+        let orderbook = orderbook();
+
+        // When
+        let bids = fee_adjusted(&orderbook, Side::Bid, &fees(), Liquidity::Maker);
+
+        // Then
+        assert_eq!(9_990.0, bids[0].effective_price);
+        assert!((bids[1].effective_price - 9_985.998_5).abs() < 1e-6);
+    }
+}
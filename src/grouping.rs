@@ -0,0 +1,179 @@
+use crate::orderbook::{
+    Orderbook,
+    Side,
+    Source,
+    EPSILON,
+};
+use std::collections::BTreeMap;
+
+/// Width of the price buckets a side is grouped into.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Bucket {
+    /// Fixed step in quote currency, e.g. 1, 5 or 10.
+    Price(f64),
+    /// Step as a percent of the mid price, buckets start at the mid.
+    PercentOfMid(f64),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum GroupingError {
+    InvalidStep(f64),
+    /// Grouping around the mid needs both sides of the book.
+    NoMid,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct GroupedLevel {
+    /// Bucket bound farthest from the touch, bids round down and asks round up, so the bucket
+    /// price is never better than any level in it.
+    pub price: f64,
+    pub size: f64,
+    pub source_size: BTreeMap<Source, f64>,
+}
+
+/// Groups one side of the merged book into coarser price buckets, summing the size of every
+/// source per bucket.
+///
+/// Venues with different tick sizes end up in the same buckets, which venue side grouping (like
+/// Deribit's `book.X.none.N` channels) cannot give.
+pub fn group(orderbook: &Orderbook, side: Side, bucket: Bucket) -> Result<Vec<GroupedLevel>, GroupingError> {
+    let (anchor, step) = match bucket {
+        Bucket::Price(step) => (0.0, step),
+        Bucket::PercentOfMid(percent) => {
+            let mid = orderbook.mid().ok_or(GroupingError::NoMid)?;
+            (mid, mid * percent / 100.0)
+        }
+    };
+    if !step.is_finite() || step <= 0.0 {
+        return Err(GroupingError::InvalidStep(step));
+    }
+
+    let mut resp: Vec<GroupedLevel> = vec![];
+    let mut last_index = None;
+    for level in orderbook.levels(side) {
+        // The tolerance keeps a price sitting on a bound, like 0.3 in 0.1 buckets, in its own bucket.
+        let steps = (level.price - anchor) / step;
+        let index = match side {
+            Side::Bid => (steps + EPSILON).floor(),
+            Side::Ask => (steps - EPSILON).ceil(),
+        } as i64;
+
+        if last_index != Some(index) {
+            last_index = Some(index);
+            resp.push(GroupedLevel {
+                price: anchor + index as f64 * step,
+                size: 0.0,
+                source_size: BTreeMap::new(),
+            });
+        }
+        let grouped = resp.last_mut().expect("a bucket was just pushed");
+        for (source, size) in &level.source_size {
+            grouped.size += size;
+            *grouped.source_size.entry(*source).or_default() += size;
+        }
+    }
+
+    Ok(resp)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        grouping::{
+            group,
+            Bucket,
+            GroupingError,
+        },
+        orderbook::{
+            Level,
+            Operation,
+            Orderbook,
+            Side,
+            Source,
+        },
+    };
+
+    fn orderbook() -> Orderbook {
+        let mut orderbook = Orderbook::new(20);
+        orderbook.process(Operation::Snapshot {
+            asks: vec![Level { price: 100.5, size: 1.0 }, Level { price: 101.0, size: 2.0 }],
+            bids: vec![Level { price: 99.5, size: 1.0 }, Level { price: 98.0, size: 4.0 }],
+            source: Source::OKX,
+            sequence: None,
+        });
+        orderbook.process(Operation::Snapshot {
+            asks: vec![Level { price: 100.25, size: 3.0 }, Level { price: 101.5, size: 1.0 }],
+            bids: vec![Level { price: 99.75, size: 2.0 }, Level { price: 99.0, size: 1.0 }],
+            source: Source::Deribit,
+            sequence: None,
+        });
+        orderbook
+    }
+
+    #[test]
+    fn should_floor_bids_and_ceil_asks() {
+        // Given
+        let orderbook = orderbook();
+
+        // When
+        let asks = group(&orderbook, Side::Ask, Bucket::Price(1.0)).unwrap();
+        let bids = group(&orderbook, Side::Bid, Bucket::Price(1.0)).unwrap();
+
+        // Then
+        let asks: Vec<(f64, f64)> = asks.iter().map(|x| (x.price, x.size)).collect();
+        assert_eq!(vec![(101.0, 6.0), (102.0, 1.0)], asks);
+        let bid_prices: Vec<f64> = bids.iter().map(|x| x.price).collect();
+        assert_eq!(vec![99.0, 98.0], bid_prices);
+        assert_eq!(Some(&3.0), bids[0].source_size.get(&Source::Deribit));
+        assert_eq!(Some(&1.0), bids[0].source_size.get(&Source::OKX));
+        assert_eq!(4.0, bids[0].size);
+    }
+
+    #[test]
+    fn should_keep_prices_on_a_bound_in_their_bucket() {
+        // Given
+        let mut orderbook = Orderbook::new(20);
+        orderbook.process(Operation::Snapshot {
+            asks: vec![Level { price: 0.3, size: 1.0 }, Level { price: 0.35, size: 1.0 }],
+            bids: vec![],
+            source: Source::OKX,
+            sequence: None,
+        });
+
+        // When
+        let asks = group(&orderbook, Side::Ask, Bucket::Price(0.1)).unwrap();
+
+        // Then
+        assert_eq!(2, asks.len());
+        assert!((asks[0].price - 0.3).abs() < 1e-9);
+        assert!((asks[1].price - 0.4).abs() < 1e-9);
+    }
+
+    #[test]
+    fn should_group_by_percent_from_mid() {
+        // Given
+        let orderbook = orderbook();
+
+        // When
+        let bids = group(&orderbook, Side::Bid, Bucket::PercentOfMid(1.0)).unwrap();
+
+        // Then
+        // The mid is 100 so the buckets are 1 wide: [99, 100) and [98, 99), bids are floored.
+        let bids: Vec<(f64, f64)> = bids.iter().map(|x| (x.price, x.size)).collect();
+        assert_eq!(vec![(99.0, 4.0), (98.0, 4.0)], bids);
+    }
+
+    #[test]
+    fn should_reject_invalid_buckets() {
+        // Given
+        let orderbook = Orderbook::new(20);
+
+        // When
+        let zero = group(&orderbook, Side::Ask, Bucket::Price(0.0));
+        let no_mid = group(&orderbook, Side::Ask, Bucket::PercentOfMid(1.0));
+
+        // Then
+        assert_eq!(Err(GroupingError::InvalidStep(0.0)), zero);
+        assert_eq!(Err(GroupingError::NoMid), no_mid);
+    }
+}
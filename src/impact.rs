@@ -0,0 +1,223 @@
+use crate::orderbook::{
+    Orderbook,
+    Side,
+    Source,
+    EPSILON,
+};
+use std::collections::BTreeMap;
+
+/// How much to trade, in base currency or in quote currency.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Amount {
+    Quantity(f64),
+    Notional(f64),
+}
+
+#[derive(Debug, PartialEq)]
+pub enum ImpactError {
+    InvalidAmount(f64),
+    /// Slippage is measured against the mid, which needs both sides of the book.
+    NoMid,
+}
+
+/// Outcome of sweeping one side of the merged book.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sweep {
+    pub quantity: f64,
+    pub notional: f64,
+    pub average_price: f64,
+    /// Price of the last level reached.
+    pub worst_price: f64,
+    /// Distance of the average price from the mid, positive when it is worse than the mid.
+    pub slippage_bps: f64,
+    /// Quantity taken from each venue.
+    pub source_quantity: BTreeMap<Source, f64>,
+    /// Part of the amount the book could not fill, in the unit it was asked in.
+    pub unfilled: f64,
+}
+
+fn slippage_bps(side: Side, price: f64, mid: f64) -> f64 {
+    match side {
+        Side::Ask => (price - mid) / mid * 10_000.0,
+        Side::Bid => (mid - price) / mid * 10_000.0,
+    }
+}
+
+impl Orderbook {
+    /// Walks `side` from the best price, as a market order taking `amount` would: buying sweeps the
+    /// asks, selling the bids.
+    ///
+    /// A level taken in part is split between its sources pro rata, the book does not know which
+    /// venue an order would actually reach first.
+    pub fn sweep(&self, side: Side, amount: Amount) -> Result<Sweep, ImpactError> {
+        let mid = self.mid().ok_or(ImpactError::NoMid)?;
+        let (mut left, by_notional) = match amount {
+            Amount::Quantity(x) => (x, false),
+            Amount::Notional(x) => (x, true),
+        };
+        if !left.is_finite() || left <= 0.0 {
+            return Err(ImpactError::InvalidAmount(left));
+        }
+
+        let mut sweep = Sweep {
+            quantity: 0.0,
+            notional: 0.0,
+            average_price: mid,
+            worst_price: mid,
+            slippage_bps: 0.0,
+            source_quantity: BTreeMap::new(),
+            unfilled: 0.0,
+        };
+        for level in self.levels(side) {
+            if left <= EPSILON {
+                break;
+            }
+            let size = level.size();
+            let available = if by_notional { size * level.price } else { size };
+            let taken = available.min(left);
+            left -= taken;
+            let quantity = if by_notional { taken / level.price } else { taken };
+
+            sweep.quantity += quantity;
+            sweep.notional += quantity * level.price;
+            sweep.worst_price = level.price;
+            for (source, source_size) in &level.source_size {
+                *sweep.source_quantity.entry(*source).or_default() += quantity * source_size / size;
+            }
+        }
+
+        if sweep.quantity > 0.0 {
+            sweep.average_price = sweep.notional / sweep.quantity;
+            sweep.slippage_bps = slippage_bps(side, sweep.average_price, mid);
+        }
+        sweep.unfilled = left.max(0.0);
+        Ok(sweep)
+    }
+
+    /// Largest quantity a sweep of `side` can take with its average price within `bps` of the mid.
+    pub fn max_quantity_within(&self, side: Side, bps: f64) -> Result<f64, ImpactError> {
+        let mid = self.mid().ok_or(ImpactError::NoMid)?;
+        if !bps.is_finite() || bps < 0.0 {
+            return Err(ImpactError::InvalidAmount(bps));
+        }
+        let limit = match side {
+            Side::Ask => mid * (1.0 + bps / 10_000.0),
+            Side::Bid => mid * (1.0 - bps / 10_000.0),
+        };
+
+        let (mut quantity, mut notional) = (0.0, 0.0);
+        for level in self.levels(side) {
+            let size = level.size();
+            let average = (notional + size * level.price) / (quantity + size);
+            if slippage_bps(side, average, mid) <= bps + EPSILON {
+                quantity += size;
+                notional += size * level.price;
+                continue;
+            }
+            // Take the part of this level that brings the average price right onto the limit.
+            if (level.price - limit).abs() > EPSILON {
+                quantity += ((limit * quantity - notional) / (level.price - limit)).clamp(0.0, size);
+            }
+            break;
+        }
+        Ok(quantity)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        impact::{
+            Amount,
+            ImpactError,
+        },
+        orderbook::{
+            Level,
+            Operation,
+            Orderbook,
+            Side,
+            Source,
+        },
+    };
+
+    fn orderbook() -> Orderbook {
+        let mut orderbook = Orderbook::new(20);
+        orderbook.process(Operation::Snapshot {
+            asks: vec![Level { price: 101.0, size: 1.0 }, Level { price: 102.0, size: 2.0 }],
+            bids: vec![Level { price: 99.0, size: 1.0 }],
+            source: Source::OKX,
+            sequence: None,
+        });
+        orderbook.process(Operation::Snapshot {
+            asks: vec![Level { price: 101.0, size: 3.0 }, Level { price: 104.0, size: 5.0 }],
+            bids: vec![Level { price: 98.0, size: 2.0 }],
+            source: Source::Deribit,
+            sequence: None,
+        });
+        orderbook
+    }
+
+    #[test]
+    fn should_sweep_a_quantity_across_venues() {
+        // Given
+        let orderbook = orderbook();
+
+        // When
+        let sweep = orderbook.sweep(Side::Ask, Amount::Quantity(5.0)).unwrap();
+
+        // Then
+        // 4 at 101 split 1 OKX / 3 Deribit, then 1 of the 2 OKX quotes at 102.
+        assert_eq!(5.0, sweep.quantity);
+        assert_eq!(506.0, sweep.notional);
+        assert!((sweep.average_price - 101.2).abs() < 1e-9);
+        assert_eq!(102.0, sweep.worst_price);
+        assert!((sweep.slippage_bps - 120.0).abs() < 1e-9);
+        assert_eq!(Some(&2.0), sweep.source_quantity.get(&Source::OKX));
+        assert_eq!(Some(&3.0), sweep.source_quantity.get(&Source::Deribit));
+        assert_eq!(0.0, sweep.unfilled);
+    }
+
+    #[test]
+    fn should_sweep_a_notional_and_report_what_is_left() {
+        // Given
+        let orderbook = orderbook();
+
+        // When
+        let sweep = orderbook.sweep(Side::Bid, Amount::Notional(500.0)).unwrap();
+
+        // Then
+        assert_eq!(3.0, sweep.quantity);
+        assert_eq!(295.0, sweep.notional);
+        assert_eq!(98.0, sweep.worst_price);
+        assert!((sweep.slippage_bps - 166.666_666).abs() < 1e-3);
+        assert_eq!(205.0, sweep.unfilled);
+    }
+
+    #[test]
+    fn should_find_the_max_quantity_within_a_slippage() {
+        // Given
+        let orderbook = orderbook();
+
+        // When
+        let at_touch = orderbook.max_quantity_within(Side::Ask, 100.0).unwrap();
+        let partial = orderbook.max_quantity_within(Side::Ask, 120.0).unwrap();
+
+        // Then
+        assert_eq!(4.0, at_touch);
+        assert!((partial - 5.0).abs() < 1e-9);
+        let sweep = orderbook.sweep(Side::Ask, Amount::Quantity(partial)).unwrap();
+        assert!((sweep.slippage_bps - 120.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn should_reject_a_sweep_without_a_mid() {
+        // Given
+        let orderbook = Orderbook::new(20);
+
+        // When
+        let resp = orderbook.sweep(Side::Ask, Amount::Quantity(1.0));
+
+        // Then
+        assert_eq!(Err(ImpactError::NoMid), resp);
+    }
+}
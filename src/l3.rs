@@ -0,0 +1,292 @@
+use crate::orderbook::{
+    Level,
+    Operation,
+    Side,
+    Source,
+    EPSILON,
+};
+use std::collections::{
+    BTreeMap,
+    HashMap,
+    VecDeque,
+};
+
+#[derive(Debug, PartialEq)]
+pub enum L3Error {
+    DuplicateOrder(String),
+    InvalidSize(f64),
+    UnknownOrder(String),
+}
+
+struct Order {
+    side: Side,
+    price_key: i64,
+    size: f64,
+}
+
+struct PriceLevel {
+    price: f64,
+    size: f64,
+    /// Order ids, first in first out.
+    queue: VecDeque<String>,
+}
+
+/// Orders ahead of a given order at its price.
+#[derive(Debug, PartialEq)]
+pub struct QueuePosition {
+    pub orders_ahead: usize,
+    pub size_ahead: f64,
+}
+
+/// Order by order book for venues that publish individual orders.
+///
+/// Orders rest in price-time priority, and every change returns the aggregated level it touched,
+/// which is what an L2 [`Operation::Update`] carries.
+#[derive(Default)]
+pub struct L3Book {
+    /// Keyed by price in `EPSILON` ticks, so equal prices share a level.
+    asks: BTreeMap<i64, PriceLevel>,
+    bids: BTreeMap<i64, PriceLevel>,
+    orders: HashMap<String, Order>,
+}
+
+fn price_key(price: f64) -> i64 {
+    (price / EPSILON).round() as i64
+}
+
+impl L3Book {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn side_mut(&mut self, side: Side) -> &mut BTreeMap<i64, PriceLevel> {
+        match side {
+            Side::Ask => &mut self.asks,
+            Side::Bid => &mut self.bids,
+        }
+    }
+
+    fn side(&self, side: Side) -> &BTreeMap<i64, PriceLevel> {
+        match side {
+            Side::Ask => &self.asks,
+            Side::Bid => &self.bids,
+        }
+    }
+
+    /// Aggregated level at `price_key`, with size zero once its last order is gone.
+    fn level(&self, side: Side, price_key: i64, price: f64) -> Level {
+        match self.side(side).get(&price_key) {
+            Some(level) => Level { price: level.price, size: level.size },
+            None => Level { price, size: 0.0 },
+        }
+    }
+
+    /// Queues a new order at the back of its price level.
+    pub fn add(&mut self, id: &str, side: Side, price: f64, size: f64) -> Result<(Side, Level), L3Error> {
+        if size <= EPSILON {
+            return Err(L3Error::InvalidSize(size));
+        }
+        if self.orders.contains_key(id) {
+            return Err(L3Error::DuplicateOrder(id.to_string()));
+        }
+
+        let key = price_key(price);
+        let level = self.side_mut(side).entry(key).or_insert_with(|| PriceLevel {
+            price,
+            size: 0.0,
+            queue: VecDeque::new(),
+        });
+        level.size += size;
+        level.queue.push_back(id.to_string());
+        self.orders.insert(id.to_string(), Order { side, price_key: key, size });
+        Ok((side, self.level(side, key, price)))
+    }
+
+    /// Takes the order out of the book.
+    pub fn remove(&mut self, id: &str) -> Result<(Side, Level), L3Error> {
+        let order = self.orders.remove(id).ok_or_else(|| L3Error::UnknownOrder(id.to_string()))?;
+        let levels = self.side_mut(order.side);
+        let price = match levels.get_mut(&order.price_key) {
+            Some(level) => {
+                level.queue.retain(|x| x != id);
+                level.size -= order.size;
+                let price = level.price;
+                if level.queue.is_empty() {
+                    levels.remove(&order.price_key);
+                }
+                price
+            }
+            None => order.price_key as f64 * EPSILON,
+        };
+        Ok((order.side, self.level(order.side, order.price_key, price)))
+    }
+
+    /// Sets the resting size of an order, as venues do on amendments.
+    ///
+    /// A smaller size keeps the order's place in the queue, a larger one sends it to the back.
+    pub fn change(&mut self, id: &str, size: f64) -> Result<(Side, Level), L3Error> {
+        if size <= EPSILON {
+            return self.remove(id);
+        }
+        let order = self.orders.get_mut(id).ok_or_else(|| L3Error::UnknownOrder(id.to_string()))?;
+        let (side, key, delta) = (order.side, order.price_key, size - order.size);
+        order.size = size;
+
+        let level = self.side_mut(side).get_mut(&key).expect("resting orders have a level");
+        level.size += delta;
+        if delta > EPSILON {
+            level.queue.retain(|x| x != id);
+            level.queue.push_back(id.to_string());
+        }
+        let price = level.price;
+        Ok((side, self.level(side, key, price)))
+    }
+
+    /// Executes `size` against the order, removing it once nothing is left.
+    pub fn fill(&mut self, id: &str, size: f64) -> Result<(Side, Level), L3Error> {
+        let remaining = self.orders.get(id)
+            .map(|x| x.size - size)
+            .ok_or_else(|| L3Error::UnknownOrder(id.to_string()))?;
+        self.change(id, remaining)
+    }
+
+    /// How many orders, and how much size, would trade before this one at its price.
+    pub fn queue_position(&self, id: &str) -> Option<QueuePosition> {
+        let order = self.orders.get(id)?;
+        let level = self.side(order.side).get(&order.price_key)?;
+        let mut position = QueuePosition { orders_ahead: 0, size_ahead: 0.0 };
+        for ahead in level.queue.iter().take_while(|x| x.as_str() != id) {
+            position.orders_ahead += 1;
+            position.size_ahead += self.orders.get(ahead).map(|x| x.size).unwrap_or_default();
+        }
+        Some(position)
+    }
+
+    pub fn len(&self) -> usize {
+        self.orders.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.orders.is_empty()
+    }
+
+    /// Best `depth` aggregated levels of `side`, in book order, all of them when `depth` is zero.
+    pub fn levels(&self, side: Side, depth: usize) -> Vec<Level> {
+        let depth = if depth == 0 { usize::MAX } else { depth };
+        let to_level = |x: &PriceLevel| Level { price: x.price, size: x.size };
+        match side {
+            Side::Ask => self.asks.values().take(depth).map(to_level).collect(),
+            Side::Bid => self.bids.values().rev().take(depth).map(to_level).collect(),
+        }
+    }
+
+    /// The L2 projection of this book, ready to be merged into an [`crate::orderbook::Orderbook`].
+    pub fn snapshot(&self, source: Source, depth: usize) -> Operation {
+        Operation::Snapshot {
+            asks: self.levels(Side::Ask, depth),
+            bids: self.levels(Side::Bid, depth),
+            source,
+            sequence: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        l3::{
+            L3Book,
+            L3Error,
+            QueuePosition,
+        },
+        orderbook::{
+            Orderbook,
+            Side,
+            Source,
+        },
+    };
+
+    fn book() -> L3Book {
+        let mut book = L3Book::new();
+        book.add("a", Side::Bid, 100.0, 1.0).unwrap();
+        book.add("b", Side::Bid, 100.0, 2.0).unwrap();
+        book.add("c", Side::Bid, 100.0, 3.0).unwrap();
+        book.add("d", Side::Bid, 99.5, 4.0).unwrap();
+        book.add("e", Side::Ask, 101.0, 5.0).unwrap();
+        book
+    }
+
+    #[test]
+    fn should_keep_price_time_priority() {
+        // Given
+        let book = book();
+
+        // When
+        let position = book.queue_position("c");
+
+        // Then
+        assert_eq!(Some(QueuePosition { orders_ahead: 2, size_ahead: 3.0 }), position);
+        assert_eq!(Some(QueuePosition { orders_ahead: 0, size_ahead: 0.0 }), book.queue_position("d"));
+    }
+
+    #[test]
+    fn should_lose_priority_only_when_the_size_grows() {
+        // Given
+        let mut book = book();
+
+        // When
+        book.change("a", 0.5).unwrap();
+        let (_, level) = book.change("b", 2.5).unwrap();
+
+        // Then
+        assert_eq!(6.0, level.size);
+        assert_eq!(Some(QueuePosition { orders_ahead: 0, size_ahead: 0.0 }), book.queue_position("a"));
+        assert_eq!(Some(QueuePosition { orders_ahead: 2, size_ahead: 3.5 }), book.queue_position("b"));
+    }
+
+    #[test]
+    fn should_report_the_touched_level() {
+        // Given
+        let mut book = book();
+
+        // When
+        let (_, partial) = book.fill("e", 2.0).unwrap();
+        let (side, gone) = book.fill("e", 3.0).unwrap();
+
+        // Then
+        assert_eq!(3.0, partial.size);
+        assert_eq!(Side::Ask, side);
+        assert_eq!((101.0, 0.0), (gone.price, gone.size));
+        assert_eq!(4, book.len());
+        assert_eq!(Err(L3Error::UnknownOrder("e".to_string())), book.remove("e").map(|_| ()));
+    }
+
+    #[test]
+    fn should_reject_a_duplicate_order() {
+        // Given
+        let mut book = book();
+
+        // When
+        let resp = book.add("a", Side::Ask, 102.0, 1.0);
+
+        // Then
+        assert_eq!(Err(L3Error::DuplicateOrder("a".to_string())), resp.map(|_| ()));
+    }
+
+    #[test]
+    fn should_project_into_the_merged_book() {
+        // Given
+        let book = book();
+        let mut orderbook = Orderbook::new(5);
+
+        // When
+        orderbook.process(book.snapshot(Source::OKX, 0));
+
+        // Then
+        assert_eq!((1, 2), orderbook.len());
+        assert_eq!(Some(101.0), orderbook.best_ask());
+        assert_eq!(Some(100.0), orderbook.best_bid());
+        let bids: Vec<(f64, f64)> = book.levels(Side::Bid, 0).iter().map(|x| (x.price, x.size)).collect();
+        assert_eq!(vec![(100.0, 6.0), (99.5, 4.0)], bids);
+    }
+}
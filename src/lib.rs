@@ -0,0 +1,21 @@
+//! Aggregated order book across crypto venues, the `top-book` binary streams it live.
+
+pub mod analytics;
+pub mod arb;
+pub mod capture;
+pub mod config;
+pub mod connector;
+pub mod deribit;
+pub mod fees;
+pub mod grouping;
+pub mod impact;
+pub mod l3;
+pub mod metrics;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+pub mod okx;
+pub mod orderbook;
+pub mod persistence;
+pub mod queue;
+pub mod routing;
+pub mod supervisor;
@@ -1,51 +1,95 @@
-use crate::{
+use top_book::{
+    analytics::Analytics,
+    arb::ArbDetector,
+    capture::{
+        self,
+        CaptureError,
+        Recorder,
+    },
+    config::{
+        Config,
+        DeribitConfig,
+        OKXConfig,
+        SupervisorConfig,
+        TelemetryConfig,
+        TraceExporter,
+    },
+    connector::{
+        self,
+        Shutdown,
+    },
     orderbook::{
-        Orderbook,
         Operation,
+        Orderbook,
+        Side,
+        Source,
+    },
+    deribit::{
+        self,
+        DeribitError,
+    },
+    metrics::{
+        self,
+        metrics,
+        MetricsError,
+    },
+    okx::{
+        self,
+        OKXError,
+    },
+    persistence::{
+        self,
+        PersistenceError,
+    },
+    queue::{
+        self,
+        OperationReceiver,
+        OperationSender,
+    },
+    supervisor::{
+        self,
+        SupervisorError,
     },
-    deribit::DeribitError,
-    okx::OKXError,
 };
-use futures_util::{
-    future,
-    pin_mut,
-    TryFutureExt,
+use clap::{
+    Parser,
+    Subcommand,
 };
-use opentelemetry_jaeger;
+use futures_util::TryFutureExt;
 use opentelemetry::{
-    global::{
-        self,
-        BoxedTracer,
+    global,
+    sdk::{
+        export::trace::stdout,
+        trace::{
+            self as sdktrace,
+            Sampler,
+        },
+        Resource,
     },
-    sdk::trace as sdktrace,
     trace::{
         FutureExt,
-        mark_span_as_active,
-        Span,
         TraceContextExt,
         Tracer,
         TraceError,
+        TracerProvider,
     },
     Context,
     Key,
-    sdk::{
-        propagation::TraceContextPropagator,
-        Resource,
-    },
-};
-use tracing_subscriber::{
-    prelude::*,
-    Registry,
+    KeyValue,
 };
+use opentelemetry_otlp::WithExportConfig;
 use tokio::{
     io::AsyncWriteExt,
-    net::TcpStream,
-    sync::mpsc::{
-        self,
-        UnboundedReceiver,
-        UnboundedSender,
+    task::{
+        JoinError,
+        JoinHandle,
     },
-    task,
+    time::Instant,
+};
+#[cfg(unix)]
+use tokio::signal::unix::{
+    signal,
+    SignalKind,
 };
 use std::{
     error::Error,
@@ -53,27 +97,79 @@ use std::{
         Display,
         Formatter,
     },
-    future::Future,
-    io,
-    net::SocketAddr,
-    thread,
+    path::{
+        Path,
+        PathBuf,
+    },
+    time::Duration,
 };
 
-mod orderbook;
-mod deribit;
-mod okx;
+fn init_tracer(config: &TelemetryConfig, venues: &[&str]) -> Result<sdktrace::Tracer, TraceError> {
+    let instance_id = config.instance_id.clone()
+        .or_else(|| std::env::var("HOSTNAME").ok())
+        .unwrap_or_else(|| std::process::id().to_string());
+    let trace_config = sdktrace::config()
+        .with_sampler(Sampler::ParentBased(Box::new(Sampler::TraceIdRatioBased(config.sampling_ratio))))
+        .with_resource(Resource::new(vec![
+            KeyValue::new("service.name", config.service_name.clone()),
+            KeyValue::new("service.instance.id", instance_id),
+            KeyValue::new("top_book.venues", venues.join(",")),
+        ]));
 
-fn init_tracer() -> Result<sdktrace::Tracer, TraceError> {
-    opentelemetry_jaeger::new_pipeline()
-        .with_service_name("top-book")
-        .install_batch(opentelemetry::runtime::Tokio)
+    match config.exporter {
+        TraceExporter::OtlpGrpc => {
+            let mut exporter = opentelemetry_otlp::new_exporter().tonic().with_env();
+            if let Some(endpoint) = &config.endpoint {
+                exporter = exporter.with_endpoint(endpoint);
+            }
+            opentelemetry_otlp::new_pipeline()
+                .tracing()
+                .with_exporter(exporter)
+                .with_trace_config(trace_config)
+                .install_batch(opentelemetry::runtime::Tokio)
+        }
+        TraceExporter::OtlpHttp => {
+            let exporter = opentelemetry_otlp::new_exporter().http().with_endpoint(
+                config.endpoint.as_deref().unwrap_or("http://localhost:4318/v1/traces"),
+            );
+            opentelemetry_otlp::new_pipeline()
+                .tracing()
+                .with_exporter(exporter)
+                .with_trace_config(trace_config)
+                .install_batch(opentelemetry::runtime::Tokio)
+        }
+        TraceExporter::Jaeger => {
+            let mut pipeline = opentelemetry_jaeger::new_pipeline()
+                .with_service_name(&config.service_name)
+                .with_trace_config(trace_config);
+            if let Some(endpoint) = &config.endpoint {
+                pipeline = pipeline.with_agent_endpoint(endpoint);
+            }
+            pipeline.install_batch(opentelemetry::runtime::Tokio)
+        }
+        TraceExporter::Stdout => {
+            Ok(stdout::new_pipeline()
+                .with_trace_config(trace_config)
+                .install_simple())
+        }
+        TraceExporter::None => {
+            let provider = sdktrace::TracerProvider::builder()
+                .with_config(trace_config)
+                .build();
+            let tracer = provider.tracer("top-book");
+            let _ = global::set_tracer_provider(provider);
+            Ok(tracer)
+        }
+    }
 }
 
 #[derive(Debug)]
 pub enum WebsocketError {
+    Capture(CaptureError),
     Orderbook,
-    Deribit(DeribitError),
-    OKX(OKXError),
+    Deribit(SupervisorError<DeribitError>),
+    Metrics(MetricsError),
+    OKX(SupervisorError<OKXError>),
 }
 
 impl Display for WebsocketError {
@@ -84,25 +180,89 @@ impl Display for WebsocketError {
 
 impl Error for WebsocketError {}
 
+type PersistWrite = JoinHandle<Result<(), PersistenceError>>;
+
+/// Starts writing the book to `path` on a blocking thread, so the processing loop never waits on
+/// the disk. Returns the write in flight, `None` when the book could not be encoded.
+async fn persist_orderbook(orderbook: &Orderbook, path: &Path) -> Option<PersistWrite> {
+    let data = match persistence::encode(orderbook) {
+        Ok(data) => data,
+        Err(err) => {
+            connector::log(format!("Could not encode the orderbook: {}\n", err)).await;
+            return None;
+        }
+    };
+    let path = path.to_path_buf();
+    Some(tokio::task::spawn_blocking(move || persistence::write(&path, &data)))
+}
+
+async fn report_persisted(written: Result<Result<(), PersistenceError>, JoinError>) {
+    match written {
+        Ok(Ok(())) => {}
+        Ok(Err(err)) => connector::log(format!("Could not save the orderbook: {}\n", err)).await,
+        Err(err) => connector::log(format!("Could not save the orderbook: {}\n", err)).await,
+    }
+}
+
+/// Applies every operation to `orderbook`, saving it to `persist` on its interval and once more
+/// when the channel is drained.
 async fn process_orderbook(
-    receiver: &mut UnboundedReceiver<Operation>,
+    receiver: &mut OperationReceiver, mut orderbook: Orderbook, stdout: bool,
+    persist: Option<(PathBuf, Duration)>, mut arb: Option<ArbDetector>, mut analytics: Option<Analytics>,
 ) -> Result<(), WebsocketError> {
     let tracer = global::tracer("orderbook_processor");
     let span = tracer.start("process_orderbook");
     let ctx = Context::current_with_span(span);
 
-    let mut orderbook = Orderbook::new(200);
-    while let Some(operation) = receiver.recv()
-        .with_context(ctx.clone())
-        .await {
-        let span = tracer.start("process_orderbook_operation");
+    let mut persist_interval = persist.as_ref()
+        .map(|(_, period)| tokio::time::interval_at(Instant::now() + *period, *period));
+    // At most one write in flight, a save is skipped while the disk is still busy with the last.
+    let mut persisting: Option<PersistWrite> = None;
+    loop {
+        let operation = tokio::select! {
+            operation = receiver.recv().with_context(ctx.clone()) => match operation {
+                Some(operation) => operation,
+                None => break,
+            },
+            _ = async {
+                match persist_interval.as_mut() {
+                    Some(interval) => interval.tick().await,
+                    None => std::future::pending().await,
+                }
+            } => {
+                if let Some(write) = persisting.as_mut() {
+                    match futures_util::FutureExt::now_or_never(write) {
+                        Some(written) => report_persisted(written).await,
+                        None => continue,
+                    }
+                }
+                if let Some((path, _)) = &persist {
+                    persisting = persist_orderbook(&orderbook, path).await;
+                }
+                continue;
+            }
+        };
+        // Each operation is its own trace so the sampling ratio applies to it.
+        let span = tracer.start_with_context("process_orderbook_operation", &Context::new());
         let ctx = Context::current_with_span(span);
         let (asks_len, bids_len) = operation.len();
         ctx.span().add_event("processing orderbook message", vec![
             Key::new("operation_asks_len").i64(asks_len as i64),
             Key::new("operation_bids_len").i64(bids_len as i64),
         ]);
+        let source = operation.source();
+        let is_status = matches!(operation, Operation::Status { .. });
+        let timer = metrics().apply_latency.start_timer();
         orderbook.process(operation);
+        timer.observe_duration();
+        metrics().observe_book(&orderbook);
+        if !is_status {
+            metrics().observe_update(source);
+        }
+        for source in orderbook.take_incomplete() {
+            connector::log(format!("{:?} levels past the depth were lost, requesting a snapshot\n", source)).await;
+            receiver.request_resync(source);
+        }
 
         let (asks_len, bids_len) = orderbook.len();
         ctx.span().add_event(
@@ -111,65 +271,281 @@ async fn process_orderbook(
                 Key::new("bids_len").i64(bids_len as i64),
             ],
         );
-        tokio::io::stdout().write_all(
+        if stdout {
+            tokio::io::stdout().write_all(
+                format!(
+                    "Orderbook size {:?}, degraded: {}, asks: {:?}, bids: {:?}\n",
+                    orderbook.len(),
+                    orderbook.is_degraded(),
+                    orderbook.levels(Side::Ask),
+                    orderbook.levels(Side::Bid),
+                ).as_bytes(),
+            )
+                .with_context(ctx.clone())
+                .await.map_err(|_| WebsocketError::Orderbook)?;
+        }
+        if let Some(analytics) = analytics.as_mut() {
+            for (scope, signals, rolling) in analytics.observe(&orderbook, Instant::now()) {
+                let line = format!("Analytics {:?}: {:?}, over the window {:?}\n", scope, signals, rolling);
+                tokio::io::stdout().write_all(line.as_bytes())
+                    .with_context(ctx.clone())
+                    .await.map_err(|_| WebsocketError::Orderbook)?;
+            }
+        }
+        if let Some(detector) = arb.as_mut() {
+            for signal in detector.observe(&orderbook, Instant::now()) {
+                tokio::io::stdout().write_all(format!("Arb signal {:?}\n", signal).as_bytes())
+                    .with_context(ctx.clone())
+                    .await.map_err(|_| WebsocketError::Orderbook)?;
+            }
+        }
+    }
+
+    // Every sender is gone and the channel is drained, this is the book as it was left.
+    if stdout {
+        let mut stdout = tokio::io::stdout();
+        stdout.write_all(
             format!(
-                "Orderbook size {:?}, content: {:?}\n",
+                "Final orderbook size {:?}, degraded: {}, asks: {:?}, bids: {:?}\n",
                 orderbook.len(),
-                orderbook,
+                orderbook.is_degraded(),
+                orderbook.levels(Side::Ask),
+                orderbook.levels(Side::Bid),
             ).as_bytes(),
-        )
-            .with_context(ctx.clone())
-            .await.map_err(|_| WebsocketError::Orderbook)?;
+        ).await.map_err(|_| WebsocketError::Orderbook)?;
+        stdout.flush().await.map_err(|_| WebsocketError::Orderbook)?;
+    }
+    if let Some(write) = persisting {
+        report_persisted(write.await).await;
+    }
+    if let Some((path, _)) = &persist {
+        if let Some(write) = persist_orderbook(&orderbook, path).await {
+            report_persisted(write.await).await;
+        }
     }
 
     Ok(())
 }
 
 async fn process_okx_ws(
-    ctx: Context, sender: UnboundedSender<Operation>,
+    ctx: Context, config: OKXConfig, supervisor: SupervisorConfig, sender: OperationSender,
+    recorder: Option<Recorder>, message_spans: bool, shutdown: Shutdown,
 ) -> Result<(), WebsocketError> {
-    okx::consume_orderbook(sender).map_err(WebsocketError::OKX)
-        .with_context(ctx)
+    if !config.enabled {
+        return Ok(());
+    }
+    supervisor::supervise(Source::OKX, supervisor, sender.clone(), shutdown.clone(), move || {
+        okx::consume_orderbook(config.clone(), sender.clone(), recorder.clone(), message_spans, shutdown.clone())
+            .with_context(ctx.clone())
+    })
+        .map_err(WebsocketError::OKX)
         .await
 }
 
 async fn process_deribit_ws(
-    ctx: Context, sender: UnboundedSender<Operation>,
+    ctx: Context, config: DeribitConfig, supervisor: SupervisorConfig, sender: OperationSender,
+    recorder: Option<Recorder>, message_spans: bool, shutdown: Shutdown,
 ) -> Result<(), WebsocketError> {
-    deribit::consume_orderbook(sender).map_err(WebsocketError::Deribit)
+    if !config.enabled {
+        return Ok(());
+    }
+    supervisor::supervise(Source::Deribit, supervisor, sender.clone(), shutdown.clone(), move || {
+        deribit::consume_orderbook(config.clone(), sender.clone(), recorder.clone(), message_spans, shutdown.clone())
+            .with_context(ctx.clone())
+    })
+        .map_err(WebsocketError::Deribit)
+        .await
+}
+
+async fn process_replay(
+    ctx: Context, sender: OperationSender, path: PathBuf, speed: f64, shutdown: Shutdown,
+) -> Result<(), WebsocketError> {
+    capture::replay(&path, speed, sender, shutdown).map_err(WebsocketError::Capture)
         .with_context(ctx)
         .await
 }
 
-#[tokio::main]
-async fn main() -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
-    let tracer = init_tracer()?;
+#[derive(Parser)]
+#[command(name = "top-book", about = "Aggregated order book across Deribit and OKX")]
+struct Cli {
+    /// TOML configuration file, the defaults are used when it is not given.
+    #[arg(short, long, global = true, env = "TOP_BOOK_CONFIG")]
+    config: Option<PathBuf>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
 
-    let span = tracer.start("root");
-    let ctx = Context::current_with_span(span);
-    ctx.span().add_event("starting application", vec![]);
+#[derive(Subcommand)]
+enum Command {
+    /// Consume the live venues, this is the default.
+    Run {
+        /// Append every raw frame to this capture file, overriding `sinks.capture`.
+        #[arg(long)]
+        record: Option<PathBuf>,
+        /// Start from the book saved at `persistence.path`, each venue resyncs on top of it.
+        #[arg(long)]
+        warm_start: bool,
+    },
+    /// Rebuild a session from a capture file.
+    Replay {
+        capture: PathBuf,
+        /// Speed factor over the original timing, 0 replays without waiting.
+        #[arg(long, default_value_t = 1.0)]
+        speed: f64,
+    },
+    /// Check the configuration, including environment overrides, and exit.
+    ValidateConfig,
+}
+
+/// Resolves on the first SIGINT or SIGTERM.
+async fn wait_for_signal() -> std::io::Result<()> {
+    #[cfg(unix)]
+    {
+        let mut terminate = signal(SignalKind::terminate())?;
+        tokio::select! {
+            interrupted = tokio::signal::ctrl_c() => interrupted,
+            _ = terminate.recv() => Ok(()),
+        }
+    }
+    #[cfg(not(unix))]
+    tokio::signal::ctrl_c().await
+}
+
+/// Runs `command` until its sources are exhausted or `shutdown` fires, then drains the channel into
+/// the book and flushes the sinks.
+async fn run(
+    ctx: Context, config: Config, command: Command, shutdown: Shutdown,
+) -> Result<(), WebsocketError> {
+    if let Some(addr) = config.metrics.listen {
+        let server = metrics::serve(addr).map_err(WebsocketError::Metrics)?;
+        tokio::spawn(async move {
+            if let Err(err) = server.await {
+                eprintln!("Metrics server stopped: {:?}", err);
+            }
+        }.with_context(ctx.clone()));
+    }
+
+    let (sender, mut receiver) = queue::channel(config.channel.capacity, config.channel.policy);
 
-    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let mut orderbook = Orderbook::new(config.depth);
+    let mut persist = None;
+    if let Command::Run { warm_start, .. } = command {
+        if let Some(path) = &config.persistence.path {
+            if warm_start {
+                match persistence::load(path, config.depth) {
+                    Ok(Some(loaded)) => orderbook = loaded,
+                    Ok(None) => connector::log(format!("No saved orderbook at {}\n", path.display())).await,
+                    // A book that cannot be read only costs the head start.
+                    Err(err) => connector::log(format!("Could not load the orderbook: {}\n", err)).await,
+                }
+            }
+            persist = Some((path.clone(), Duration::from_secs(config.persistence.interval_secs)));
+        }
+    }
 
-    let process_ob = process_orderbook(&mut receiver).
+    let arb = config.arb.enabled
+        .then(|| ArbDetector::new(config.fees.clone(), Duration::from_millis(config.arb.debounce_ms)));
+    let analytics = config.analytics.enabled
+        .then(|| Analytics::new(
+            config.analytics.top_n, config.analytics.depth_bps, Duration::from_millis(config.analytics.window_ms),
+        ));
+    let process_ob = process_orderbook(&mut receiver, orderbook, config.sinks.stdout, persist, arb, analytics).
         with_context(ctx.clone());
 
-    let okx_deribit = futures_util::future::join(
-        process_okx_ws(ctx.clone(), sender.clone()),
-        process_deribit_ws(ctx.clone(), sender.clone()),
-    )
-        .with_context(ctx.clone());
+    match command {
+        Command::Run { record, .. } => {
+            let recorder = match record.or(config.sinks.capture) {
+                Some(path) => Some(Recorder::open(&path).map_err(WebsocketError::Capture)?),
+                None => None,
+            };
 
-    let ((okx, deribit), orderbook) = futures_util::future::join(
-        okx_deribit,
-        process_ob,
-    )
-        .with_context(ctx.clone()).await;
+            let okx_deribit = futures_util::future::join(
+                process_okx_ws(
+                    ctx.clone(), config.venues.okx, config.supervisor.clone(), sender.clone(),
+                    recorder.clone(), config.telemetry.message_spans, shutdown.clone(),
+                ),
+                process_deribit_ws(
+                    ctx.clone(), config.venues.deribit, config.supervisor, sender.clone(),
+                    recorder.clone(), config.telemetry.message_spans, shutdown,
+                ),
+            )
+                .with_context(ctx.clone());
+            drop(sender);
 
-    okx?;
-    deribit?;
-    orderbook?;
+            let ((okx, deribit), orderbook) = futures_util::future::join(
+                okx_deribit,
+                process_ob,
+            )
+                .with_context(ctx.clone()).await;
+
+            if let Some(recorder) = recorder {
+                recorder.finish().map_err(WebsocketError::Capture)?;
+            }
+            okx?;
+            deribit?;
+            orderbook?;
+        }
+        Command::Replay { capture, speed } => {
+            let (replay, orderbook) = futures_util::future::join(
+                process_replay(ctx.clone(), sender, capture, speed, shutdown),
+                process_ob,
+            )
+                .with_context(ctx.clone()).await;
+
+            replay?;
+            orderbook?;
+        }
+        Command::ValidateConfig => {}
+    }
 
-    global::shutdown_tracer_provider();
     Ok(())
 }
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn Error + Send + Sync + 'static>> {
+    let cli = Cli::parse();
+    let config = match Config::load(cli.config.as_deref()) {
+        Ok(config) => config,
+        Err(err) => {
+            eprintln!("{}", err);
+            std::process::exit(1);
+        }
+    };
+    let command = cli.command.unwrap_or(Command::Run { record: None, warm_start: false });
+    if let Command::ValidateConfig = command {
+        println!("Configuration is valid");
+        return Ok(());
+    }
+    if let Command::Run { warm_start: true, .. } = command {
+        if config.persistence.path.is_none() {
+            eprintln!("--warm-start needs persistence.path");
+            std::process::exit(1);
+        }
+    }
+
+    let tracer = init_tracer(&config.telemetry, &config.venues.enabled_names())?;
+
+    let span = tracer.start("root");
+    let ctx = Context::current_with_span(span);
+    ctx.span().add_event("starting application", vec![]);
+
+    let (trigger, shutdown) = connector::shutdown();
+    tokio::spawn(async move {
+        if let Err(err) = wait_for_signal().await {
+            eprintln!("Could not listen for signals: {:?}", err);
+            return;
+        }
+        eprintln!("Shutting down, signal again to exit right away");
+        trigger.trigger();
+        if wait_for_signal().await.is_ok() {
+            std::process::exit(130);
+        }
+    });
+
+    let result = run(ctx.clone(), config, command, shutdown).await;
+    ctx.span().add_event("stopping application", vec![]);
+    ctx.span().end();
+    // Exports whatever spans are still buffered, even when the pipeline failed.
+    global::shutdown_tracer_provider();
+    Ok(result?)
+}
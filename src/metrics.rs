@@ -0,0 +1,353 @@
+use crate::orderbook::{
+    Orderbook,
+    Source,
+    VenueStatus,
+};
+use futures_util::TryFutureExt;
+use hyper::{
+    service::{
+        make_service_fn,
+        service_fn,
+    },
+    Body,
+    Method,
+    Request,
+    Response,
+    Server,
+    StatusCode,
+};
+use prometheus::{
+    Encoder,
+    Gauge,
+    GaugeVec,
+    Histogram,
+    HistogramOpts,
+    IntCounterVec,
+    IntGauge,
+    IntGaugeVec,
+    Opts,
+    Registry,
+    TextEncoder,
+};
+use std::{
+    convert::Infallible,
+    future::Future,
+    net::SocketAddr,
+    sync::LazyLock,
+    time::{
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+
+#[derive(Debug)]
+pub enum MetricsError {
+    Server(hyper::Error),
+}
+
+pub struct Metrics {
+    registry: Registry,
+    pub messages_received: IntCounterVec,
+    pub parse_errors: IntCounterVec,
+    pub connector_errors: IntCounterVec,
+    pub operations: IntCounterVec,
+    pub reconnects: IntCounterVec,
+    pub restarts: IntCounterVec,
+    pub queue_depth: IntGauge,
+    pub queue_high_water_mark: IntGauge,
+    pub queue_dropped: IntCounterVec,
+    pub queue_conflated: IntCounterVec,
+    pub apply_latency: Histogram,
+    pub book_depth: IntGaugeVec,
+    pub spread: Gauge,
+    venue_status: IntGaugeVec,
+    degraded: IntGauge,
+    last_update: GaugeVec,
+    seconds_since_last_update: GaugeVec,
+    pub portfolio_equity: GaugeVec,
+    pub portfolio_available_funds: GaugeVec,
+}
+
+static METRICS: LazyLock<Metrics> = LazyLock::new(Metrics::new);
+
+/// Process wide metrics, registered on first use.
+pub fn metrics() -> &'static Metrics {
+    &METRICS
+}
+
+fn now_seconds() -> f64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|x| x.as_secs_f64())
+        .unwrap_or_default()
+}
+
+impl Metrics {
+    fn new() -> Self {
+        let registry = Registry::new_custom(Some("top_book".to_string()), None)
+            .expect("valid metrics prefix");
+        let messages_received = IntCounterVec::new(
+            Opts::new("messages_received_total", "Websocket frames received per source"),
+            &["source"],
+        ).expect("valid metric");
+        let parse_errors = IntCounterVec::new(
+            Opts::new("parse_errors_total", "Frames that could not be decoded per source"),
+            &["source"],
+        ).expect("valid metric");
+        let connector_errors = IntCounterVec::new(
+            Opts::new("connector_errors_total", "Connector errors per source and handling policy"),
+            &["source", "policy"],
+        ).expect("valid metric");
+        let operations = IntCounterVec::new(
+            Opts::new("operations_total", "Book operations per source and kind"),
+            &["source", "kind"],
+        ).expect("valid metric");
+        let reconnects = IntCounterVec::new(
+            Opts::new("reconnects_total", "Websocket sessions re-established per source"),
+            &["source"],
+        ).expect("valid metric");
+        let restarts = IntCounterVec::new(
+            Opts::new("connector_restarts_total", "Connector tasks restarted by the supervisor per source"),
+            &["source"],
+        ).expect("valid metric");
+        let queue_depth = IntGauge::new(
+            "channel_queue_depth", "Operations waiting to be applied to the book",
+        ).expect("valid metric");
+        let queue_high_water_mark = IntGauge::new(
+            "channel_queue_high_water_mark", "Most operations ever waiting to be applied to the book",
+        ).expect("valid metric");
+        let queue_dropped = IntCounterVec::new(
+            Opts::new("channel_dropped_total", "Operations dropped by a full channel per source"),
+            &["source"],
+        ).expect("valid metric");
+        let queue_conflated = IntCounterVec::new(
+            Opts::new("channel_conflated_total", "Operations merged into a pending one per source"),
+            &["source"],
+        ).expect("valid metric");
+        let apply_latency = Histogram::with_opts(
+            HistogramOpts::new("apply_latency_seconds", "Time to apply one operation to the book")
+                .buckets(prometheus::exponential_buckets(1e-6, 4.0, 10).expect("valid buckets")),
+        ).expect("valid metric");
+        let book_depth = IntGaugeVec::new(
+            Opts::new("book_depth", "Levels in the merged book per side"),
+            &["side"],
+        ).expect("valid metric");
+        let spread = Gauge::new(
+            "spread", "Best ask minus best bid of the merged book",
+        ).expect("valid metric");
+        let venue_status = IntGaugeVec::new(
+            Opts::new("venue_status", "1 for the current status of each source, 0 for the others"),
+            &["source", "status"],
+        ).expect("valid metric");
+        let degraded = IntGauge::new(
+            "degraded", "1 while some source is not up and the book is built from the others",
+        ).expect("valid metric");
+        let last_update = GaugeVec::new(
+            Opts::new("last_update_timestamp_seconds", "Unix time of the last operation per source"),
+            &["source"],
+        ).expect("valid metric");
+        let seconds_since_last_update = GaugeVec::new(
+            Opts::new("seconds_since_last_update", "Seconds since the last operation per source"),
+            &["source"],
+        ).expect("valid metric");
+        let portfolio_equity = GaugeVec::new(
+            Opts::new("portfolio_equity", "Account equity per source and currency, from the private channels"),
+            &["source", "currency"],
+        ).expect("valid metric");
+        let portfolio_available_funds = GaugeVec::new(
+            Opts::new("portfolio_available_funds", "Funds free for new orders per source and currency"),
+            &["source", "currency"],
+        ).expect("valid metric");
+
+        registry.register(Box::new(messages_received.clone())).expect("unique metric");
+        registry.register(Box::new(parse_errors.clone())).expect("unique metric");
+        registry.register(Box::new(connector_errors.clone())).expect("unique metric");
+        registry.register(Box::new(operations.clone())).expect("unique metric");
+        registry.register(Box::new(reconnects.clone())).expect("unique metric");
+        registry.register(Box::new(restarts.clone())).expect("unique metric");
+        registry.register(Box::new(queue_depth.clone())).expect("unique metric");
+        registry.register(Box::new(queue_high_water_mark.clone())).expect("unique metric");
+        registry.register(Box::new(queue_dropped.clone())).expect("unique metric");
+        registry.register(Box::new(queue_conflated.clone())).expect("unique metric");
+        registry.register(Box::new(apply_latency.clone())).expect("unique metric");
+        registry.register(Box::new(book_depth.clone())).expect("unique metric");
+        registry.register(Box::new(spread.clone())).expect("unique metric");
+        registry.register(Box::new(venue_status.clone())).expect("unique metric");
+        registry.register(Box::new(degraded.clone())).expect("unique metric");
+        registry.register(Box::new(last_update.clone())).expect("unique metric");
+        registry.register(Box::new(seconds_since_last_update.clone())).expect("unique metric");
+        registry.register(Box::new(portfolio_equity.clone())).expect("unique metric");
+        registry.register(Box::new(portfolio_available_funds.clone())).expect("unique metric");
+
+        let metrics = Self {
+            registry,
+            messages_received,
+            parse_errors,
+            connector_errors,
+            operations,
+            reconnects,
+            restarts,
+            queue_depth,
+            queue_high_water_mark,
+            queue_dropped,
+            queue_conflated,
+            apply_latency,
+            book_depth,
+            spread,
+            venue_status,
+            degraded,
+            last_update,
+            seconds_since_last_update,
+            portfolio_equity,
+            portfolio_available_funds,
+        };
+        // Export every per-source counter from the start so rate() sees the first increment.
+        for source in [Source::Deribit, Source::OKX] {
+            let label = Metrics::source_label(source);
+            metrics.messages_received.with_label_values(&[label]);
+            metrics.parse_errors.with_label_values(&[label]);
+            metrics.reconnects.with_label_values(&[label]);
+            metrics.restarts.with_label_values(&[label]);
+            metrics.queue_dropped.with_label_values(&[label]);
+            metrics.queue_conflated.with_label_values(&[label]);
+        }
+        metrics
+    }
+
+    pub fn source_label(source: Source) -> &'static str {
+        match source {
+            Source::Deribit => "deribit",
+            Source::OKX => "okx",
+        }
+    }
+
+    pub fn observe_book(&self, orderbook: &Orderbook) {
+        let (asks_len, bids_len) = orderbook.len();
+        self.book_depth.with_label_values(&["ask"]).set(asks_len as i64);
+        self.book_depth.with_label_values(&["bid"]).set(bids_len as i64);
+        // Without both sides there is no spread, a stale one would look like a live market.
+        match (orderbook.best_ask(), orderbook.best_bid()) {
+            (Some(ask), Some(bid)) => self.spread.set(ask - bid),
+            _ => self.spread.set(f64::NAN),
+        }
+        for (source, status) in orderbook.statuses() {
+            for candidate in [VenueStatus::Up, VenueStatus::Down, VenueStatus::Resyncing] {
+                self.venue_status.with_label_values(&[Metrics::source_label(source), candidate.label()])
+                    .set((candidate == status) as i64);
+            }
+        }
+        self.degraded.set(orderbook.is_degraded() as i64);
+    }
+
+    /// Stamps the last book data received from `source`, status changes do not count.
+    pub fn observe_update(&self, source: Source) {
+        self.last_update.with_label_values(&[Metrics::source_label(source)]).set(now_seconds());
+    }
+
+    /// Renders every metric in the Prometheus text format.
+    pub fn render(&self) -> Vec<u8> {
+        let now = now_seconds();
+        for source in [Source::Deribit, Source::OKX] {
+            let label = Metrics::source_label(source);
+            if let Ok(last_update) = self.last_update.get_metric_with_label_values(&[label]) {
+                if last_update.get() > 0.0 {
+                    self.seconds_since_last_update.with_label_values(&[label])
+                        .set(now - last_update.get());
+                }
+            }
+        }
+
+        let mut buffer = vec![];
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)
+            .expect("text encoding into a vec");
+        buffer
+    }
+}
+
+async fn handle(request: Request<Body>) -> Result<Response<Body>, Infallible> {
+    let response = if request.method() == Method::GET && request.uri().path() == "/metrics" {
+        Response::builder()
+            .header(hyper::header::CONTENT_TYPE, TextEncoder::new().format_type())
+            .body(Body::from(metrics().render()))
+    } else {
+        Response::builder()
+            .status(StatusCode::NOT_FOUND)
+            .body(Body::empty())
+    };
+    Ok(response.expect("valid response"))
+}
+
+/// Binds `addr` and returns the server answering `/metrics`, binding errors are reported right away.
+pub fn serve(addr: SocketAddr) -> Result<impl Future<Output=Result<(), MetricsError>>, MetricsError> {
+    let make_service = make_service_fn(|_| async {
+        Ok::<_, Infallible>(service_fn(handle))
+    });
+    let server = Server::try_bind(&addr).map_err(MetricsError::Server)?
+        .serve(make_service);
+    Ok(server.map_err(MetricsError::Server))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        metrics::Metrics,
+        orderbook::{
+            Level,
+            Operation,
+            Orderbook,
+            Source,
+            VenueStatus,
+        },
+    };
+
+    #[test]
+    fn should_render_book_metrics() {
+        // Given
+        let metrics = Metrics::new();
+        let mut orderbook = Orderbook::new(5);
+        orderbook.process(Operation::Snapshot {
+            asks: vec![Level { price: 101.0, size: 1.0 }],
+            bids: vec![Level { price: 99.5, size: 1.0 }, Level { price: 99.0, size: 2.0 }],
+            source: Source::OKX,
+            sequence: None,
+        });
+
+        // When
+        metrics.messages_received.with_label_values(&["okx"]).inc();
+        metrics.observe_book(&orderbook);
+        metrics.observe_update(Source::OKX);
+        let rendered = String::from_utf8(metrics.render()).unwrap();
+
+        // Then
+        assert!(rendered.contains("top_book_messages_received_total{source=\"okx\"} 1"));
+        assert!(rendered.contains("top_book_book_depth{side=\"ask\"} 1"));
+        assert!(rendered.contains("top_book_book_depth{side=\"bid\"} 2"));
+        assert!(rendered.contains("top_book_spread 1.5"));
+        assert!(rendered.contains("top_book_seconds_since_last_update{source=\"okx\"}"));
+        assert!(rendered.contains("top_book_venue_status{source=\"okx\",status=\"up\"} 1"));
+        assert!(rendered.contains("top_book_degraded 0"));
+    }
+
+    #[test]
+    fn should_clear_the_spread_of_a_one_sided_book_and_not_stamp_statuses() {
+        // Given
+        let metrics = Metrics::new();
+        let mut orderbook = Orderbook::new(5);
+        orderbook.process(Operation::Snapshot {
+            asks: vec![Level { price: 101.0, size: 1.0 }],
+            bids: vec![Level { price: 99.5, size: 1.0 }],
+            source: Source::OKX,
+            sequence: None,
+        });
+        metrics.observe_book(&orderbook);
+
+        // When
+        orderbook.process(Operation::Status { source: Source::OKX, status: VenueStatus::Down });
+        metrics.observe_book(&orderbook);
+        let rendered = String::from_utf8(metrics.render()).unwrap();
+
+        // Then
+        assert!(rendered.contains("top_book_spread NaN"));
+        assert!(!rendered.contains("top_book_seconds_since_last_update{source=\"okx\"}"));
+    }
+}
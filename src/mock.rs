@@ -0,0 +1,333 @@
+use crate::orderbook::{
+    Level,
+    Source,
+};
+use futures_util::{
+    SinkExt,
+    StreamExt,
+};
+use serde_json::{
+    json,
+    Value,
+};
+use std::{
+    collections::VecDeque,
+    sync::{
+        Arc,
+        Mutex,
+    },
+    time::{
+        Duration,
+        SystemTime,
+        UNIX_EPOCH,
+    },
+};
+use tokio::{
+    net::{
+        TcpListener,
+        TcpStream,
+    },
+    task::JoinHandle,
+};
+use tokio_tungstenite::{
+    accept_async,
+    tungstenite::Message,
+    WebSocketStream,
+};
+
+/// Sequence id of the first book message of a session.
+const FIRST_SEQUENCE: i64 = 1_000;
+/// Lifetime of the tokens granted outside of an [`Step::Authenticate`].
+const TOKEN_LIFETIME_SECS: u64 = 900;
+
+/// One step of a scripted session.
+#[derive(Clone)]
+pub enum Step {
+    /// Waits for a subscribe request and confirms it, answering any other request on the way.
+    Subscribe,
+    /// Waits for a Deribit `public/auth` and grants it tokens valid for that many seconds,
+    /// answering any other request on the way.
+    Authenticate { expires_in_secs: u64 },
+    /// Waits for a Deribit `public/auth`, a login or a refresh, and turns it down.
+    RejectAuth,
+    Snapshot { asks: Vec<Level>, bids: Vec<Level> },
+    /// An update following the last book message, a size of zero deletes the level.
+    Update { asks: Vec<Level>, bids: Vec<Level> },
+    /// Skips a sequence id, as if an update had been lost on the way.
+    Gap,
+    /// Sends a frame as is.
+    Raw(String),
+    /// Asks the client for a sign of life: a `test_request` heartbeat on Deribit, nothing on OKX
+    /// whose clients ping on their own.
+    TestRequest,
+    Sleep(Duration),
+    /// Reads requests without answering any until the client leaves, as a dead venue would.
+    Stall,
+    /// Drops the connection without a close frame.
+    Disconnect,
+}
+
+/// Local websocket server speaking the protocol of a venue, for end-to-end tests of its connector.
+///
+/// Every connection plays the next session script, then answers requests until the client leaves.
+/// Connections past the last script are only answered.
+pub struct MockServer {
+    url: String,
+    requests: Arc<Mutex<Vec<Value>>>,
+    task: JoinHandle<()>,
+}
+
+impl MockServer {
+    /// Listens on a free port of localhost.
+    pub async fn start(source: Source, sessions: Vec<Vec<Step>>) -> std::io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let url = format!("ws://{}/", listener.local_addr()?);
+        let requests = Arc::new(Mutex::new(vec![]));
+        let task = tokio::spawn(serve(listener, source, sessions.into(), requests.clone()));
+        Ok(Self { url, requests, task })
+    }
+
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    /// Every request received so far, over all sessions.
+    pub fn requests(&self) -> Vec<Value> {
+        self.requests.lock().expect("requests lock").clone()
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn serve(listener: TcpListener, source: Source, mut sessions: VecDeque<Vec<Step>>, requests: Arc<Mutex<Vec<Value>>>) {
+    // One connection at a time, a connector only opens the next once the last one is gone.
+    while let Ok((stream, _)) = listener.accept().await {
+        let ws = match accept_async(stream).await {
+            Ok(ws) => ws,
+            Err(_) => continue,
+        };
+        let mut session = Session {
+            source,
+            ws,
+            requests: requests.clone(),
+            subscription: Value::Null,
+            sequence: FIRST_SEQUENCE,
+            tokens: 0,
+        };
+        session.play(sessions.pop_front().unwrap_or_default()).await;
+    }
+}
+
+struct Session {
+    source: Source,
+    ws: WebSocketStream<TcpStream>,
+    requests: Arc<Mutex<Vec<Value>>>,
+    /// What the last subscribe request asked for, echoed in book messages.
+    subscription: Value,
+    sequence: i64,
+    /// Tokens granted so far, numbering the next ones.
+    tokens: u64,
+}
+
+impl Session {
+    async fn play(&mut self, steps: Vec<Step>) {
+        for step in steps {
+            let sent = match step {
+                Step::Subscribe => self.subscribe().await,
+                Step::Authenticate { expires_in_secs } => match self.expect(is_auth).await {
+                    Some(request) => {
+                        let answer = reply(&request, self.grant(expires_in_secs));
+                        self.send(answer).await
+                    }
+                    None => false,
+                },
+                Step::RejectAuth => match self.expect(is_auth).await {
+                    Some(request) => {
+                        let error = json!({"code": 13004, "message": "invalid_credentials"});
+                        self.send(json!({"jsonrpc": "2.0", "id": request["id"], "error": error})).await
+                    }
+                    None => false,
+                },
+                Step::Snapshot { asks, bids } => {
+                    let message = self.book_message(true, &asks, &bids);
+                    self.send(message).await
+                }
+                Step::Update { asks, bids } => {
+                    self.sequence += 1;
+                    let message = self.book_message(false, &asks, &bids);
+                    self.send(message).await
+                }
+                Step::Gap => {
+                    self.sequence += 1;
+                    true
+                }
+                Step::Raw(text) => self.ws.send(Message::Text(text)).await.is_ok(),
+                Step::TestRequest => match self.source {
+                    Source::Deribit => {
+                        let message = json!({"jsonrpc": "2.0", "method": "heartbeat", "params": {"type": "test_request"}});
+                        self.send(message).await
+                    }
+                    Source::OKX => true,
+                },
+                Step::Sleep(duration) => {
+                    tokio::time::sleep(duration).await;
+                    true
+                }
+                Step::Stall => {
+                    while self.request().await.is_some() {}
+                    return;
+                }
+                Step::Disconnect => return,
+            };
+            if !sent {
+                return;
+            }
+        }
+        while let Some(request) = self.request().await {
+            if !self.answer(&request).await {
+                return;
+            }
+        }
+    }
+
+    /// Next request of the client, `None` once it is gone. Pings are answered by tungstenite.
+    async fn request(&mut self) -> Option<Value> {
+        loop {
+            match self.ws.next().await? {
+                Ok(Message::Text(text)) => {
+                    let request = serde_json::from_str(&text).unwrap_or(Value::String(text));
+                    self.requests.lock().expect("requests lock").push(request.clone());
+                    return Some(request);
+                }
+                Ok(Message::Close(_)) | Err(_) => return None,
+                Ok(_) => {}
+            }
+        }
+    }
+
+    /// Answers requests until one that is `expected` comes, which is given back unanswered.
+    async fn expect(&mut self, expected: impl Fn(&Value) -> bool) -> Option<Value> {
+        while let Some(request) = self.request().await {
+            if expected(&request) {
+                return Some(request);
+            }
+            if !self.answer(&request).await {
+                return None;
+            }
+        }
+        None
+    }
+
+    async fn subscribe(&mut self) -> bool {
+        let source = self.source;
+        let request = match self.expect(|x| match source {
+            Source::Deribit => x["method"] == "public/subscribe",
+            Source::OKX => x["op"] == "subscribe",
+        }).await {
+            Some(request) => request,
+            None => return false,
+        };
+        self.subscription = match self.source {
+            Source::Deribit => request["params"]["channels"][0].clone(),
+            Source::OKX => request["args"][0].clone(),
+        };
+        self.answer(&request).await
+    }
+
+    /// Result of a successful `public/auth`.
+    fn grant(&mut self, expires_in_secs: u64) -> Value {
+        self.tokens += 1;
+        json!({
+            "access_token": format!("access-{}", self.tokens),
+            "refresh_token": format!("refresh-{}", self.tokens),
+            "expires_in": expires_in_secs,
+            "token_type": "bearer",
+            "scope": "session:mock",
+        })
+    }
+
+    /// Answers a request the way the venue does.
+    async fn answer(&mut self, request: &Value) -> bool {
+        let answer = match self.source {
+            Source::Deribit => {
+                let result = match request["method"].as_str() {
+                    Some("public/set_heartbeat") => json!("ok"),
+                    Some("public/test") => json!({"version": "mock"}),
+                    Some("public/auth") => self.grant(TOKEN_LIFETIME_SECS),
+                    Some("private/get_open_orders_by_instrument") => json!([]),
+                    _ => request["params"]["channels"].clone(),
+                };
+                reply(request, result)
+            }
+            Source::OKX if request == "ping" => {
+                return self.ws.send(Message::Text("pong".to_string())).await.is_ok();
+            }
+            Source::OKX => json!({"event": request["op"], "arg": request["args"][0]}),
+        };
+        self.send(answer).await
+    }
+
+    fn book_message(&self, snapshot: bool, asks: &[Level], bids: &[Level]) -> Value {
+        let timestamp = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as u64;
+        match self.source {
+            Source::Deribit => {
+                let levels = |levels: &[Level]| -> Vec<Value> {
+                    levels.iter()
+                        .map(|x| {
+                            let action = if snapshot { "new" } else if x.size == 0.0 { "delete" } else { "change" };
+                            json!([action, x.price, x.size])
+                        })
+                        .collect()
+                };
+                let mut data = json!({
+                    "type": if snapshot { "snapshot" } else { "change" },
+                    "timestamp": timestamp,
+                    "change_id": self.sequence,
+                    "asks": levels(asks),
+                    "bids": levels(bids),
+                });
+                if !snapshot {
+                    data["prev_change_id"] = json!(self.sequence - 1);
+                }
+                json!({
+                    "jsonrpc": "2.0",
+                    "method": "subscription",
+                    "params": {"channel": self.subscription, "data": data},
+                })
+            }
+            Source::OKX => {
+                let levels = |levels: &[Level]| -> Vec<Value> {
+                    levels.iter().map(|x| json!([x.price.to_string(), x.size.to_string(), "0", "1"])).collect()
+                };
+                json!({
+                    "arg": self.subscription,
+                    "action": if snapshot { "snapshot" } else { "update" },
+                    "data": [{
+                        "asks": levels(asks),
+                        "bids": levels(bids),
+                        "ts": timestamp.to_string(),
+                        "seqId": self.sequence,
+                        "prevSeqId": if snapshot { -1 } else { self.sequence - 1 },
+                    }],
+                })
+            }
+        }
+    }
+
+    async fn send(&mut self, message: Value) -> bool {
+        self.ws.send(Message::Text(message.to_string())).await.is_ok()
+    }
+}
+
+/// Deribit reply to `request`.
+fn reply(request: &Value, result: Value) -> Value {
+    json!({"jsonrpc": "2.0", "id": request["id"], "result": result})
+}
+
+fn is_auth(request: &Value) -> bool {
+    request["method"] == "public/auth"
+}
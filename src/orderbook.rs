@@ -1,23 +1,99 @@
-use std::collections::HashMap;
+use serde_derive::{
+    Deserialize,
+    Serialize,
+};
+use std::{
+    borrow::Cow,
+    collections::{
+        BTreeMap,
+        BTreeSet,
+        HashMap,
+    },
+};
+
+pub(crate) const EPSILON: f64 = 1e-5;
 
-const EPSILON: f64 = 1e-5;
+/// Levels kept per side for every visible one, so levels pushed past the depth can move back up
+/// when better ones are deleted.
+const BUFFER_FACTOR: usize = 2;
 
-#[derive(Debug, PartialOrd, PartialEq, Eq, Clone, Copy, Hash)]
+#[derive(Debug, PartialOrd, Ord, PartialEq, Eq, Clone, Copy, Hash, Serialize, Deserialize)]
 pub enum Source {
     Deribit,
     OKX,
 }
 
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Hash)]
+pub enum Side {
+    Ask,
+    Bid,
+}
+
+/// Health of a venue as reported by its connector.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub enum VenueStatus {
+    /// The book holds the venue's current levels.
+    Up,
+    /// The venue's levels were removed, the connector is reconnecting or gave up.
+    Down,
+    /// A fresh snapshot was requested, the venue's levels may be stale until it arrives.
+    Resyncing,
+}
+
+impl VenueStatus {
+    pub fn label(&self) -> &'static str {
+        match self {
+            VenueStatus::Up => "up",
+            VenueStatus::Down => "down",
+            VenueStatus::Resyncing => "resyncing",
+        }
+    }
+}
+
 #[derive(Clone)]
 pub struct Level {
     pub price: f64,
     pub size: f64,
 }
 
-#[derive(Clone, Debug)]
-struct LevelInfo {
-    price: f64,
-    source_size: HashMap<Source, f64>,
+impl Level {
+    /// Whether the book can take this level: a finite price and a finite size, zero to delete.
+    pub fn is_valid(&self) -> bool {
+        self.price.is_finite() && self.size.is_finite() && self.size >= 0.0
+    }
+}
+
+/// Where an operation sits in its venue's stream.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Serialize, Deserialize)]
+pub struct Sequence {
+    /// Venue sequence number, `seqId` on OKX and `change_id` on Deribit.
+    pub id: i64,
+    /// Venue timestamp in milliseconds since the Unix epoch.
+    pub timestamp_ms: u64,
+}
+
+/// One price of the merged book with the size each source quotes there.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct LevelInfo {
+    pub price: f64,
+    pub source_size: HashMap<Source, f64>,
+}
+
+impl LevelInfo {
+    pub fn size(&self) -> f64 {
+        self.source_size.values().sum()
+    }
+}
+
+/// One of our orders on a venue.
+#[derive(Clone, Debug, PartialEq)]
+pub struct OwnOrder {
+    pub id: String,
+    pub side: Side,
+    pub price: f64,
+    /// Size still resting in the book, zero once the order is filled, cancelled or was never
+    /// resting to begin with.
+    pub size: f64,
 }
 
 pub enum Operation {
@@ -25,11 +101,23 @@ pub enum Operation {
         asks: Vec<Level>,
         bids: Vec<Level>,
         source: Source,
+        sequence: Option<Sequence>,
     },
     Update {
         asks: Vec<Level>,
         bids: Vec<Level>,
         source: Source,
+        sequence: Option<Sequence>,
+    },
+    Status {
+        status: VenueStatus,
+        source: Source,
+    },
+    /// Changes to our orders, or with `snapshot` every order still open.
+    Orders {
+        orders: Vec<OwnOrder>,
+        source: Source,
+        snapshot: bool,
     },
 }
 
@@ -38,21 +126,154 @@ impl Operation {
         match self {
             Operation::Snapshot { asks, bids, .. } => (asks.len(), bids.len()),
             Operation::Update { asks, bids, .. } => (asks.len(), bids.len()),
+            Operation::Status { .. } => (0, 0),
+            Operation::Orders { orders, .. } => (
+                orders.iter().filter(|x| x.side == Side::Ask).count(),
+                orders.iter().filter(|x| x.side == Side::Bid).count(),
+            ),
+        }
+    }
+
+    pub fn source(&self) -> Source {
+        match self {
+            Operation::Snapshot { source, .. } => *source,
+            Operation::Update { source, .. } => *source,
+            Operation::Status { source, .. } => *source,
+            Operation::Orders { source, .. } => *source,
+        }
+    }
+
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Operation::Snapshot { .. } => "snapshot",
+            Operation::Update { .. } => "update",
+            Operation::Status { .. } => "status",
+            Operation::Orders { .. } => "orders",
+        }
+    }
+
+    fn conflate_side(older: Vec<Level>, newer: Vec<Level>, side_multiplier: f64, keep_empty: bool) -> Vec<Level> {
+        let mut resp = Vec::with_capacity(older.len() + newer.len());
+        let mut older = older.into_iter().peekable();
+        let mut newer = newer.into_iter().peekable();
+        loop {
+            let level = match (older.peek(), newer.peek()) {
+                (Some(old), Some(new)) => {
+                    if (old.price - new.price).abs() < EPSILON {
+                        older.next();
+                        newer.next()
+                    } else if old.price * side_multiplier < new.price * side_multiplier {
+                        older.next()
+                    } else {
+                        newer.next()
+                    }
+                }
+                (Some(_), None) => older.next(),
+                (None, Some(_)) => newer.next(),
+                (None, None) => break,
+            };
+            if let Some(level) = level {
+                if keep_empty || level.size > EPSILON {
+                    resp.push(level);
+                }
+            }
+        }
+        resp
+    }
+
+    /// Merges `newer`, from the same source, into this operation, so applying the result is the
+    /// same as applying both in order. Statuses and orders are never merged, `newer` is given back
+    /// for them.
+    pub fn conflate(&mut self, newer: Operation) -> Option<Operation> {
+        match (self, newer) {
+            (Operation::Status { .. } | Operation::Orders { .. }, newer) |
+            (_, newer @ (Operation::Status { .. } | Operation::Orders { .. })) => Some(newer),
+            (older, newer @ Operation::Snapshot { .. }) => {
+                *older = newer;
+                None
+            }
+            (
+                Operation::Snapshot { asks, bids, sequence, .. },
+                Operation::Update { asks: new_asks, bids: new_bids, sequence: new_sequence, .. },
+            ) => {
+                *asks = Operation::conflate_side(std::mem::take(asks), new_asks, 1.0, false);
+                *bids = Operation::conflate_side(std::mem::take(bids), new_bids, -1.0, false);
+                *sequence = new_sequence.or(*sequence);
+                None
+            }
+            (
+                Operation::Update { asks, bids, sequence, .. },
+                Operation::Update { asks: new_asks, bids: new_bids, sequence: new_sequence, .. },
+            ) => {
+                *asks = Operation::conflate_side(std::mem::take(asks), new_asks, 1.0, true);
+                *bids = Operation::conflate_side(std::mem::take(bids), new_bids, -1.0, true);
+                *sequence = new_sequence.or(*sequence);
+                None
+            }
         }
     }
 }
 
-#[derive(Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Orderbook {
+    /// Up to `depth * BUFFER_FACTOR` levels, the first `depth` are visible.
     asks: Vec<LevelInfo>,
     bids: Vec<LevelInfo>,
     depth: usize,
+    statuses: BTreeMap<Source, VenueStatus>,
+    /// Last sequence applied per source, for sources whose venue sends one.
+    sequences: BTreeMap<Source, Sequence>,
+    /// Best price of the levels each source lost to the buffer cap, until its next snapshot the
+    /// book only knows that source's side up to there.
+    ask_horizons: BTreeMap<Source, f64>,
+    bid_horizons: BTreeMap<Source, f64>,
+    /// Sources already reported by `take_incomplete` since their last snapshot.
+    #[serde(skip)]
+    incomplete_reported: BTreeSet<Source>,
+    /// Our resting orders per source and id, only as fresh as the private channels, so never saved.
+    #[serde(skip)]
+    own_orders: BTreeMap<Source, BTreeMap<String, OwnOrder>>,
 }
 
 impl Orderbook {
+    /// Levels of an operation in book order with one level per price, the last one winning as it
+    /// would if they were applied one by one.
+    ///
+    /// Venues send them that way already, so this only copies when they do not.
+    fn normalize(levels: &[Level], side_multiplier: f64) -> Cow<'_, [Level]> {
+        let in_order = levels.windows(2)
+            .all(|x| x[0].price * side_multiplier < x[1].price * side_multiplier && (x[1].price - x[0].price).abs() >= EPSILON);
+        if in_order {
+            return Cow::Borrowed(levels);
+        }
+
+        let mut resp = levels.to_vec();
+        // Stable, so equal prices keep the order they came in.
+        resp.sort_by(|a, b| (a.price * side_multiplier).total_cmp(&(b.price * side_multiplier)));
+        let mut deduped: Vec<Level> = Vec::with_capacity(resp.len());
+        for level in resp {
+            match deduped.last_mut() {
+                Some(last) if (last.price - level.price).abs() < EPSILON => *last = level,
+                _ => deduped.push(level),
+            }
+        }
+        Cow::Owned(deduped)
+    }
+
+    /// Remembers that `source` lost its level at `price`, keeping the best such price.
+    fn note_dropped(horizons: &mut BTreeMap<Source, f64>, source: Source, price: f64, side_multiplier: f64) {
+        horizons.entry(source)
+            .and_modify(|x| {
+                if price * side_multiplier < *x * side_multiplier {
+                    *x = price;
+                }
+            })
+            .or_insert(price);
+    }
+
     fn process_side(
-        self_book: &mut Vec<LevelInfo>, source: Source, update_book: &[Level], depth: usize,
-        side_multiplier: f64,
+        self_book: &mut [LevelInfo], source: Source, update_book: &[Level], depth: usize,
+        side_multiplier: f64, horizons: &mut BTreeMap<Source, f64>,
     ) -> Vec<LevelInfo> {
         let mut resp: Vec<LevelInfo> = Vec::with_capacity(depth);
         let mut self_it = 0;
@@ -101,45 +322,306 @@ impl Orderbook {
             update_it += 1;
         }
 
+        // Whatever is left did not fit, in book order so the first one per source is its best.
+        for level in &self_book[self_it..] {
+            for dropped in level.source_size.keys() {
+                Orderbook::note_dropped(horizons, *dropped, level.price, side_multiplier);
+            }
+        }
+        for level in update_book[update_it..].iter().filter(|x| x.size > EPSILON) {
+            Orderbook::note_dropped(horizons, source, level.price, side_multiplier);
+        }
+
         resp
     }
 
+    /// Levels kept per side, zero for no limit.
+    fn buffer(&self) -> usize {
+        self.depth * BUFFER_FACTOR
+    }
+
     fn process_asks(&mut self, source: Source, asks: &[Level]) {
+        let buffer = self.buffer();
         self.asks = Orderbook::process_side(
-            &mut self.asks, source, asks, self.depth, 1.0,
+            &mut self.asks, source, &Orderbook::normalize(asks, 1.0), buffer, 1.0, &mut self.ask_horizons,
         );
     }
 
     fn process_bids(&mut self, source: Source, bids: &[Level]) {
+        let buffer = self.buffer();
         self.bids = Orderbook::process_side(
-            &mut self.bids, source, bids, self.depth, -1.0,
+            &mut self.bids, source, &Orderbook::normalize(bids, -1.0), buffer, -1.0, &mut self.bid_horizons,
         );
     }
 
+    fn remove_source(&mut self, source: Source) {
+        self.ask_horizons.remove(&source);
+        self.bid_horizons.remove(&source);
+        self.incomplete_reported.remove(&source);
+        self.asks = self.asks.iter_mut().map(|x| {
+            x.source_size.remove(&source);
+            x.clone()
+        }).filter(|x| !x.source_size.is_empty()).collect();
+        self.bids = self.bids.iter_mut().map(|x| {
+            x.source_size.remove(&source);
+            x.clone()
+        }).filter(|x| !x.source_size.is_empty()).collect();
+    }
+
     pub fn process(&mut self, operation: Operation) {
         match operation {
-            Operation::Snapshot { asks, bids, source } => {
-                self.asks = self.asks.iter_mut().map(|x| {
-                    x.source_size.remove(&source);
-                    x.clone()
-                }).filter(|x| !x.source_size.is_empty()).collect();
-                self.bids = self.bids.iter_mut().map(|x| {
-                    x.source_size.remove(&source);
-                    x.clone()
-                }).filter(|x| !x.source_size.is_empty()).collect();
-
+            Operation::Snapshot { asks, bids, source, sequence } => {
+                self.remove_source(source);
                 self.process_asks(source, &asks);
                 self.process_bids(source, &bids);
+                self.statuses.insert(source, VenueStatus::Up);
+                self.record_sequence(source, sequence);
             }
-            Operation::Update { asks, bids, source } => {
+            Operation::Update { asks, bids, source, sequence } => {
                 self.process_asks(source, &asks);
                 self.process_bids(source, &bids);
+                self.record_sequence(source, sequence);
+            }
+            Operation::Status { status, source } => {
+                // Levels of a venue that is gone would be quoted as if they were still there.
+                if status == VenueStatus::Down {
+                    self.remove_source(source);
+                }
+                self.statuses.insert(source, status);
+            }
+            Operation::Orders { orders, source, snapshot } => self.update_own_orders(source, orders, snapshot),
+        }
+    }
+
+    /// Takes in changes to our orders on `source`, or with `snapshot` every order still open there,
+    /// for order feeds other than the venue connectors.
+    pub fn update_own_orders(&mut self, source: Source, orders: Vec<OwnOrder>, snapshot: bool) {
+        let own_orders = self.own_orders.entry(source).or_default();
+        if snapshot {
+            own_orders.clear();
+        }
+        for order in orders {
+            if order.size > EPSILON {
+                own_orders.insert(order.id.clone(), order);
+            } else {
+                own_orders.remove(&order.id);
+            }
+        }
+    }
+
+    fn record_sequence(&mut self, source: Source, sequence: Option<Sequence>) {
+        if let Some(sequence) = sequence {
+            self.sequences.insert(source, sequence);
+        }
+    }
+
+    /// Marks every known source as resyncing, for a book restored from disk whose levels are only
+    /// right until each venue sends a new snapshot.
+    pub fn mark_pending_resync(&mut self) {
+        let sources: Vec<Source> = self.asks.iter().chain(self.bids.iter())
+            .flat_map(|x| x.source_size.keys().copied())
+            .chain(self.statuses.keys().copied())
+            .collect();
+        for source in sources {
+            self.statuses.insert(source, VenueStatus::Resyncing);
+        }
+    }
+
+    /// Shows at most `depth` levels per side from now on.
+    pub fn set_depth(&mut self, depth: usize) {
+        self.depth = depth;
+        let buffer = self.buffer();
+        if buffer == 0 {
+            return;
+        }
+        for (book, horizons, side_multiplier) in [
+            (&mut self.asks, &mut self.ask_horizons, 1.0),
+            (&mut self.bids, &mut self.bid_horizons, -1.0),
+        ] {
+            for level in book.iter().skip(buffer) {
+                for source in level.source_size.keys() {
+                    Orderbook::note_dropped(horizons, *source, level.price, side_multiplier);
+                }
             }
+            book.truncate(buffer);
         }
     }
 
+    /// Sources that may be missing levels from the visible `side`.
+    ///
+    /// A source that lost levels to the buffer cap is only known up to the best of them, the
+    /// visible side is complete while it holds `depth` levels better than that.
+    pub fn incomplete_sources(&self, side: Side) -> Vec<Source> {
+        let (book, horizons, side_multiplier) = match side {
+            Side::Ask => (&self.asks, &self.ask_horizons, 1.0),
+            Side::Bid => (&self.bids, &self.bid_horizons, -1.0),
+        };
+        horizons.iter()
+            .filter(|(_, horizon)| {
+                let known = book.iter()
+                    .take_while(|x| x.price * side_multiplier < **horizon * side_multiplier - EPSILON)
+                    .count();
+                known < self.depth
+            })
+            .map(|(source, _)| *source)
+            .collect()
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.incomplete_sources(Side::Ask).is_empty() && self.incomplete_sources(Side::Bid).is_empty()
+    }
+
+    /// Sources that left the visible depth incomplete, each one once until its next snapshot, so
+    /// the caller can ask its venue for one.
+    pub fn take_incomplete(&mut self) -> Vec<Source> {
+        let mut sources = self.incomplete_sources(Side::Ask);
+        sources.extend(self.incomplete_sources(Side::Bid));
+        sources.retain(|x| self.incomplete_reported.insert(*x));
+        sources
+    }
+
+    pub fn statuses(&self) -> impl Iterator<Item=(Source, VenueStatus)> + '_ {
+        self.statuses.iter().map(|(source, status)| (*source, *status))
+    }
+
+    /// Whether some venue that reported in is not up, the book is then built from the others.
+    pub fn is_degraded(&self) -> bool {
+        self.statuses.values().any(|x| *x != VenueStatus::Up)
+    }
+
+    /// Visible levels per side.
     pub fn len(&self) -> (usize, usize) {
-        (self.asks.len(), self.bids.len())
+        (self.levels(Side::Ask).len(), self.levels(Side::Bid).len())
+    }
+
+    pub fn best_ask(&self) -> Option<f64> {
+        self.asks.first().map(|x| x.price)
+    }
+
+    pub fn best_bid(&self) -> Option<f64> {
+        self.bids.first().map(|x| x.price)
+    }
+
+    /// Our orders resting on `source`.
+    pub fn own_orders(&self, source: Source) -> impl Iterator<Item=&OwnOrder> + '_ {
+        self.own_orders.get(&source).into_iter().flat_map(|x| x.values())
+    }
+
+    /// Takes `order` out of the size its source quotes at its price, the level goes once nothing
+    /// is left of it.
+    fn subtract_own_order(book: &mut Vec<LevelInfo>, source: Source, order: &OwnOrder) {
+        let position = match book.iter().position(|x| (x.price - order.price).abs() < EPSILON) {
+            Some(position) => position,
+            None => return,
+        };
+        let level = &mut book[position];
+        if let Some(size) = level.source_size.get_mut(&source) {
+            *size -= order.size;
+            if *size <= EPSILON {
+                level.source_size.remove(&source);
+            }
+        }
+        if level.source_size.is_empty() {
+            book.remove(position);
+        }
+    }
+
+    /// The book as the rest of the market quotes it: the size of our resting orders taken out of
+    /// their source and price, so decisions made on it do not trade against ourselves.
+    ///
+    /// Our orders are only as fresh as their feed, one the venue book does not show yet still
+    /// takes its size from others. Borrowed as is while we have no orders.
+    pub fn external(&self) -> Cow<'_, Orderbook> {
+        if self.own_orders.values().all(|x| x.is_empty()) {
+            return Cow::Borrowed(self);
+        }
+        let mut resp = self.clone();
+        for (source, orders) in std::mem::take(&mut resp.own_orders) {
+            for order in orders.values() {
+                let book = match order.side {
+                    Side::Ask => &mut resp.asks,
+                    Side::Bid => &mut resp.bids,
+                };
+                Orderbook::subtract_own_order(book, source, order);
+            }
+        }
+        Cow::Owned(resp)
+    }
+
+    /// Levels `source` quotes on `side`, best first, with that source's size only.
+    pub fn source_levels(&self, side: Side, source: Source) -> impl Iterator<Item=Level> + '_ {
+        self.levels(side).iter().filter_map(move |x| {
+            x.source_size.get(&source)
+                .filter(|size| **size > EPSILON)
+                .map(|size| Level { price: x.price, size: *size })
+        })
+    }
+
+    /// Best level `source` quotes on `side`, with that source's size only.
+    pub fn best_level(&self, side: Side, source: Source) -> Option<Level> {
+        self.source_levels(side, source).next()
+    }
+
+    /// Best level `source` quotes on `side` once our resting orders are taken out of it, as
+    /// [`Orderbook::external`] shows it but without copying the book. Only the visible levels are
+    /// looked at.
+    pub fn best_external_level(&self, side: Side, source: Source) -> Option<Level> {
+        let orders = self.own_orders.get(&source);
+        self.source_levels(side, source)
+            .map(|level| {
+                let own: f64 = orders.into_iter()
+                    .flat_map(|x| x.values())
+                    .filter(|x| x.side == side && (x.price - level.price).abs() < EPSILON)
+                    .map(|x| x.size)
+                    .sum();
+                Level { size: level.size - own, ..level }
+            })
+            .find(|x| x.size > EPSILON)
+    }
+
+    pub fn mid(&self) -> Option<f64> {
+        Some((self.best_ask()? + self.best_bid()?) / 2.0)
+    }
+
+    /// Visible levels of `side` from the best price outwards.
+    pub fn levels(&self, side: Side) -> &[LevelInfo] {
+        let book = match side {
+            Side::Ask => &self.asks,
+            Side::Bid => &self.bids,
+        };
+        match self.depth {
+            0 => book,
+            depth => &book[..book.len().min(depth)],
+        }
+    }
+
+    /// The book of one venue, as if it was the only one.
+    pub fn source(&self, source: Source) -> SourceView<'_> {
+        SourceView { orderbook: self, source }
+    }
+
+    /// Share of the size in the merged top `top_n` levels of each side that every venue quotes.
+    pub fn venue_shares(&self, top_n: usize) -> Vec<VenueShare> {
+        let share = |side: Side, source: Source| {
+            let levels = &self.levels(side)[..self.levels(side).len().min(top_n)];
+            let total: f64 = levels.iter().map(|x| x.size()).sum();
+            if total <= EPSILON {
+                return 0.0;
+            }
+            levels.iter().filter_map(|x| x.source_size.get(&source)).sum::<f64>() / total
+        };
+        self.statuses.keys()
+            .map(|source| {
+                let view = self.source(*source);
+                VenueShare {
+                    source: *source,
+                    best_ask: view.best_ask(),
+                    best_bid: view.best_bid(),
+                    ask_share: share(Side::Ask, *source),
+                    bid_share: share(Side::Bid, *source),
+                }
+            })
+            .collect()
     }
 
     pub fn new(depth: usize) -> Self {
@@ -147,16 +629,80 @@ impl Orderbook {
             asks: vec![],
             bids: vec![],
             depth,
+            statuses: BTreeMap::new(),
+            sequences: BTreeMap::new(),
+            ask_horizons: BTreeMap::new(),
+            bid_horizons: BTreeMap::new(),
+            incomplete_reported: BTreeSet::new(),
+            own_orders: BTreeMap::new(),
         }
     }
 }
 
+/// One venue's side of the merged book, read from the merged levels.
+pub struct SourceView<'a> {
+    orderbook: &'a Orderbook,
+    source: Source,
+}
+
+impl<'a> SourceView<'a> {
+    pub fn status(&self) -> Option<VenueStatus> {
+        self.orderbook.statuses.get(&self.source).copied()
+    }
+
+    pub fn best_ask(&self) -> Option<f64> {
+        self.orderbook.best_level(Side::Ask, self.source).map(|x| x.price)
+    }
+
+    pub fn best_bid(&self) -> Option<f64> {
+        self.orderbook.best_level(Side::Bid, self.source).map(|x| x.price)
+    }
+
+    pub fn len(&self) -> (usize, usize) {
+        (self.levels(Side::Ask).count(), self.levels(Side::Bid).count())
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == (0, 0)
+    }
+
+    pub fn levels(&self, side: Side) -> impl Iterator<Item=Level> + 'a {
+        self.orderbook.source_levels(side, self.source)
+    }
+}
+
+/// How a venue compares with the merged book.
+#[derive(Debug, Clone, PartialEq)]
+pub struct VenueShare {
+    pub source: Source,
+    pub best_ask: Option<f64>,
+    pub best_bid: Option<f64>,
+    /// From 0 to 1.
+    pub ask_share: f64,
+    pub bid_share: f64,
+}
+
+#[cfg(test)]
 mod test {
     use crate::orderbook::{
         Level,
         Operation,
         Orderbook,
+        OwnOrder,
+        Side,
         Source,
+        VenueStatus,
+        BUFFER_FACTOR,
+        EPSILON,
+    };
+    use proptest::{
+        collection::vec,
+        prelude::*,
+        sample::select,
+    };
+    use std::{
+        borrow::Cow,
+        collections::BTreeMap,
     };
 
     #[test]
@@ -176,6 +722,7 @@ mod test {
                 Level { price: 8475.55, size: 1.0 },
             ],
             source: Source::OKX,
+            sequence: None,
         });
 
         // Then
@@ -204,6 +751,7 @@ mod test {
                 Level { price: 8475.55, size: 1.0 },
             ],
             source: Source::OKX,
+            sequence: None,
         });
         assert_eq!((2, 2), orderbook.len());
         orderbook.process(Operation::Update {
@@ -214,6 +762,7 @@ mod test {
                 Level { price: 8477.97, size: 1.0 },
             ],
             source: Source::OKX,
+            sequence: None,
         });
 
         // Then
@@ -224,4 +773,593 @@ mod test {
         assert_eq!(8477.97, orderbook.bids[0].price);
         assert_eq!(8476.97, orderbook.bids[1].price);
     }
+
+    #[test]
+    fn should_conflate_updates_like_applying_them_in_order() {
+        // Given
+        let first = Operation::Update {
+            asks: vec![
+                Level { price: 10.0, size: 1.0 },
+                Level { price: 11.0, size: 1.0 },
+            ],
+            bids: vec![Level { price: 9.0, size: 1.0 }],
+            source: Source::OKX,
+            sequence: None,
+        };
+        let second = Operation::Update {
+            asks: vec![
+                Level { price: 10.0, size: 0.0 },
+                Level { price: 10.5, size: 2.0 },
+            ],
+            bids: vec![Level { price: 9.5, size: 3.0 }],
+            source: Source::OKX,
+            sequence: None,
+        };
+
+        // When
+        let mut conflated = first;
+        assert!(conflated.conflate(second).is_none());
+
+        // Then
+        if let Operation::Update { asks, bids, .. } = conflated {
+            let asks: Vec<(f64, f64)> = asks.iter().map(|x| (x.price, x.size)).collect();
+            let bids: Vec<(f64, f64)> = bids.iter().map(|x| (x.price, x.size)).collect();
+            assert_eq!(vec![(10.0, 0.0), (10.5, 2.0), (11.0, 1.0)], asks);
+            assert_eq!(vec![(9.5, 3.0), (9.0, 1.0)], bids);
+        } else {
+            panic!("not an update");
+        }
+    }
+
+    #[test]
+    fn should_conflate_an_update_into_a_snapshot() {
+        // Given
+        let snapshot = Operation::Snapshot {
+            asks: vec![Level { price: 10.0, size: 1.0 }],
+            bids: vec![Level { price: 9.0, size: 1.0 }],
+            source: Source::Deribit,
+            sequence: None,
+        };
+        let update = Operation::Update {
+            asks: vec![Level { price: 10.0, size: 0.0 }],
+            bids: vec![],
+            source: Source::Deribit,
+            sequence: None,
+        };
+
+        // When
+        let mut conflated = snapshot;
+        assert!(conflated.conflate(update).is_none());
+
+        // Then
+        assert_eq!("snapshot", conflated.kind());
+        assert_eq!((0, 1), conflated.len());
+    }
+
+    #[test]
+    fn should_keep_the_other_venues_when_one_goes_down() {
+        // Given
+        let mut orderbook = Orderbook::new(5);
+        orderbook.process(Operation::Snapshot {
+            asks: vec![Level { price: 10.0, size: 1.0 }],
+            bids: vec![Level { price: 9.0, size: 1.0 }],
+            source: Source::OKX,
+            sequence: None,
+        });
+        orderbook.process(Operation::Snapshot {
+            asks: vec![Level { price: 10.0, size: 2.0 }, Level { price: 11.0, size: 1.0 }],
+            bids: vec![],
+            source: Source::Deribit,
+            sequence: None,
+        });
+        assert!(!orderbook.is_degraded());
+
+        // When
+        orderbook.process(Operation::Status { status: VenueStatus::Down, source: Source::Deribit });
+
+        // Then
+        assert_eq!((1, 1), orderbook.len());
+        assert_eq!(Some(10.0), orderbook.best_ask());
+        assert_eq!(Some(&VenueStatus::Down), orderbook.statuses.get(&Source::Deribit));
+        assert!(orderbook.is_degraded());
+    }
+
+    #[test]
+    fn should_mark_a_venue_up_on_its_snapshot() {
+        // Given
+        let mut orderbook = Orderbook::new(5);
+        orderbook.process(Operation::Status { status: VenueStatus::Resyncing, source: Source::OKX });
+        assert!(orderbook.is_degraded());
+
+        // When
+        orderbook.process(Operation::Snapshot {
+            asks: vec![Level { price: 10.0, size: 1.0 }],
+            bids: vec![],
+            source: Source::OKX,
+            sequence: None,
+        });
+
+        // Then
+        assert_eq!(Some(&VenueStatus::Up), orderbook.statuses.get(&Source::OKX));
+        assert!(!orderbook.is_degraded());
+    }
+
+    #[test]
+    fn should_not_conflate_statuses() {
+        // Given
+        let mut pending = Operation::Update {
+            asks: vec![Level { price: 10.0, size: 1.0 }],
+            bids: vec![],
+            source: Source::OKX,
+            sequence: None,
+        };
+
+        // When
+        let resp = pending.conflate(Operation::Status { status: VenueStatus::Down, source: Source::OKX });
+
+        // Then
+        assert_eq!(Some("status"), resp.map(|x| x.kind()));
+        assert_eq!("update", pending.kind());
+    }
+
+    #[test]
+    fn should_view_each_venue_on_its_own() {
+        // Given
+        let mut orderbook = Orderbook::new(5);
+        orderbook.process(Operation::Snapshot {
+            asks: vec![Level { price: 10.0, size: 1.0 }, Level { price: 11.0, size: 2.0 }],
+            bids: vec![Level { price: 9.0, size: 3.0 }],
+            source: Source::OKX,
+            sequence: None,
+        });
+        orderbook.process(Operation::Snapshot {
+            asks: vec![Level { price: 10.5, size: 1.0 }, Level { price: 11.0, size: 2.0 }],
+            bids: vec![],
+            source: Source::Deribit,
+            sequence: None,
+        });
+
+        // When
+        let deribit = orderbook.source(Source::Deribit);
+
+        // Then
+        assert_eq!((Some(10.5), None), (deribit.best_ask(), deribit.best_bid()));
+        assert_eq!((2, 0), deribit.len());
+        let asks: Vec<(f64, f64)> = deribit.levels(Side::Ask).map(|x| (x.price, x.size)).collect();
+        assert_eq!(vec![(10.5, 1.0), (11.0, 2.0)], asks);
+        assert_eq!(Some(VenueStatus::Up), deribit.status());
+        assert_eq!(Some(10.0), orderbook.source(Source::OKX).best_ask());
+    }
+
+    #[test]
+    fn should_report_the_venue_share_of_the_top_levels() {
+        // Given
+        let mut orderbook = Orderbook::new(5);
+        orderbook.process(Operation::Snapshot {
+            asks: vec![Level { price: 10.0, size: 1.0 }, Level { price: 11.0, size: 2.0 }],
+            bids: vec![Level { price: 9.0, size: 3.0 }],
+            source: Source::OKX,
+            sequence: None,
+        });
+        orderbook.process(Operation::Snapshot {
+            asks: vec![Level { price: 10.5, size: 3.0 }, Level { price: 11.0, size: 2.0 }],
+            bids: vec![],
+            source: Source::Deribit,
+            sequence: None,
+        });
+
+        // When
+        let shares = orderbook.venue_shares(2);
+
+        // Then
+        let shares: Vec<(Source, f64, f64)> = shares.iter().map(|x| (x.source, x.ask_share, x.bid_share)).collect();
+        assert_eq!(vec![(Source::Deribit, 0.75, 0.0), (Source::OKX, 0.25, 1.0)], shares);
+    }
+
+    fn asks(prices: &[f64], size: f64) -> Vec<Level> {
+        prices.iter().map(|price| Level { price: *price, size }).collect()
+    }
+
+    #[test]
+    fn should_bring_back_levels_pushed_past_the_depth() {
+        // Given
+        let mut orderbook = Orderbook::new(2);
+        orderbook.process(Operation::Snapshot {
+            asks: asks(&[1.0, 2.0, 3.0, 4.0], 1.0),
+            bids: vec![],
+            source: Source::OKX,
+            sequence: None,
+        });
+
+        // When
+        orderbook.process(Operation::Update {
+            asks: asks(&[1.0], 0.0),
+            bids: vec![],
+            source: Source::OKX,
+            sequence: None,
+        });
+
+        // Then
+        let prices: Vec<f64> = orderbook.levels(Side::Ask).iter().map(|x| x.price).collect();
+        assert_eq!(vec![2.0, 3.0], prices);
+        assert!(orderbook.is_complete());
+    }
+
+    #[test]
+    fn should_report_the_depth_incomplete_once_dropped_levels_are_needed() {
+        // Given
+        let mut orderbook = Orderbook::new(2);
+        orderbook.process(Operation::Snapshot {
+            asks: asks(&[1.0, 2.0, 3.0, 4.0, 5.0, 6.0], 1.0),
+            bids: vec![],
+            source: Source::OKX,
+            sequence: None,
+        });
+        orderbook.process(Operation::Update {
+            asks: asks(&[1.0, 2.0], 0.0),
+            bids: vec![],
+            source: Source::OKX,
+            sequence: None,
+        });
+        assert!(orderbook.is_complete());
+
+        // When
+        orderbook.process(Operation::Update {
+            asks: asks(&[3.0], 0.0),
+            bids: vec![],
+            source: Source::OKX,
+            sequence: None,
+        });
+
+        // Then
+        // 5 and 6 were cut at the snapshot, 4 alone is all the book knows is there.
+        assert_eq!((1, 0), orderbook.len());
+        assert_eq!(vec![Source::OKX], orderbook.incomplete_sources(Side::Ask));
+        assert_eq!(vec![Source::OKX], orderbook.take_incomplete());
+        assert!(orderbook.take_incomplete().is_empty());
+        orderbook.process(Operation::Snapshot {
+            asks: asks(&[4.0, 5.0, 6.0], 1.0),
+            bids: vec![],
+            source: Source::OKX,
+            sequence: None,
+        });
+        assert!(orderbook.is_complete());
+        assert_eq!((2, 0), orderbook.len());
+    }
+
+    #[test]
+    fn should_only_blame_the_source_that_lost_levels() {
+        // Given
+        let mut orderbook = Orderbook::new(1);
+        orderbook.process(Operation::Snapshot {
+            asks: asks(&[1.0, 2.0, 3.0], 1.0),
+            bids: vec![],
+            source: Source::OKX,
+            sequence: None,
+        });
+
+        // When
+        orderbook.process(Operation::Update {
+            asks: asks(&[1.0, 2.0], 0.0),
+            bids: vec![],
+            source: Source::OKX,
+            sequence: None,
+        });
+        orderbook.process(Operation::Snapshot {
+            asks: asks(&[2.5], 1.0),
+            bids: vec![],
+            source: Source::Deribit,
+            sequence: None,
+        });
+
+        // Then
+        // Deribit's 2.5 is visible, but OKX was cut at 3 and may quote better than that.
+        assert_eq!(Some(2.5), orderbook.best_ask());
+        assert!(orderbook.is_complete());
+        orderbook.process(Operation::Update {
+            asks: asks(&[2.5], 0.0),
+            bids: vec![],
+            source: Source::Deribit,
+            sequence: None,
+        });
+        assert_eq!(vec![Source::OKX], orderbook.incomplete_sources(Side::Ask));
+    }
+
+    fn order(id: &str, side: Side, price: f64, size: f64) -> OwnOrder {
+        OwnOrder { id: id.to_string(), side, price, size }
+    }
+
+    #[test]
+    fn should_track_the_own_orders_still_resting() {
+        // Given
+        let mut orderbook = Orderbook::new(5);
+        orderbook.process(Operation::Orders {
+            orders: vec![order("a", Side::Ask, 10.0, 1.0), order("b", Side::Bid, 9.0, 2.0)],
+            source: Source::Deribit,
+            snapshot: true,
+        });
+
+        // When
+        orderbook.process(Operation::Orders {
+            orders: vec![order("a", Side::Ask, 10.0, 0.0), order("c", Side::Bid, 8.5, 1.0)],
+            source: Source::Deribit,
+            snapshot: false,
+        });
+        orderbook.process(Operation::Status { status: VenueStatus::Down, source: Source::Deribit });
+
+        // Then
+        // Orders rest on the venue whatever the state of our connection to it.
+        let ids: Vec<&str> = orderbook.own_orders(Source::Deribit).map(|x| x.id.as_str()).collect();
+        assert_eq!(vec!["b", "c"], ids);
+        assert_eq!(0, orderbook.own_orders(Source::OKX).count());
+        orderbook.process(Operation::Orders {
+            orders: vec![order("d", Side::Ask, 11.0, 1.0)],
+            source: Source::Deribit,
+            snapshot: true,
+        });
+        let orders: Vec<OwnOrder> = orderbook.own_orders(Source::Deribit).cloned().collect();
+        assert_eq!(vec![order("d", Side::Ask, 11.0, 1.0)], orders);
+    }
+
+    #[test]
+    fn should_take_our_orders_out_of_the_external_view() {
+        // Given
+        let mut orderbook = Orderbook::new(5);
+        orderbook.process(Operation::Snapshot {
+            asks: asks(&[10.0, 11.0], 3.0),
+            bids: vec![Level { price: 9.0, size: 2.0 }],
+            source: Source::Deribit,
+            sequence: None,
+        });
+        orderbook.process(Operation::Snapshot {
+            asks: asks(&[10.0], 1.0),
+            bids: vec![Level { price: 8.0, size: 1.0 }],
+            source: Source::OKX,
+            sequence: None,
+        });
+        assert!(matches!(orderbook.external(), Cow::Borrowed(_)));
+
+        // When
+        orderbook.update_own_orders(Source::Deribit, vec![
+            order("a", Side::Ask, 10.0, 1.0),
+            order("b", Side::Bid, 9.0, 2.0),
+            order("c", Side::Ask, 12.0, 1.0),
+        ], true);
+        let external = orderbook.external();
+
+        // Then
+        let ask = &external.levels(Side::Ask)[0];
+        assert_eq!((10.0, 2.0, 1.0), (ask.price, ask.source_size[&Source::Deribit], ask.source_size[&Source::OKX]));
+        // Only we quoted 9, the bids start at the OKX 8 now.
+        assert_eq!(Some(8.0), external.best_bid());
+        assert_eq!(None, external.source(Source::Deribit).best_bid());
+        assert_eq!(0, external.own_orders(Source::Deribit).count());
+        for (side, source) in [(Side::Ask, Source::Deribit), (Side::Bid, Source::Deribit), (Side::Bid, Source::OKX)] {
+            let level = |x: Option<Level>| x.map(|x| (x.price, x.size));
+            assert_eq!(level(external.best_level(side, source)), level(orderbook.best_external_level(side, source)));
+        }
+        assert_eq!(Some(9.0), orderbook.best_bid());
+        assert_eq!(3.0, orderbook.levels(Side::Ask)[0].source_size[&Source::Deribit]);
+    }
+
+    /// Price grid of the generated levels, prices are `tick * TICK` so they compare exactly.
+    const TICK: f64 = 0.5;
+
+    type MergedSide = Vec<(u32, BTreeMap<Source, f64>)>;
+
+    #[derive(Debug, Clone)]
+    struct Generated {
+        source: Source,
+        snapshot: bool,
+        /// Ticks and sizes in the order they are sent, a zero size deletes.
+        asks: Vec<(u32, f64)>,
+        bids: Vec<(u32, f64)>,
+    }
+
+    impl Generated {
+        fn operation(&self) -> Operation {
+            let levels = |x: &[(u32, f64)]| x.iter()
+                .map(|(tick, size)| Level { price: *tick as f64 * TICK, size: *size })
+                .collect();
+            match self.snapshot {
+                true => Operation::Snapshot {
+                    asks: levels(&self.asks),
+                    bids: levels(&self.bids),
+                    source: self.source,
+                    sequence: None,
+                },
+                false => Operation::Update {
+                    asks: levels(&self.asks),
+                    bids: levels(&self.bids),
+                    source: self.source,
+                    sequence: None,
+                },
+            }
+        }
+    }
+
+    /// Every source's levels in their own map, merged when read.
+    #[derive(Default)]
+    struct Reference {
+        asks: BTreeMap<Source, BTreeMap<u32, f64>>,
+        bids: BTreeMap<Source, BTreeMap<u32, f64>>,
+    }
+
+    impl Reference {
+        fn process(&mut self, operation: &Generated) {
+            for (book, levels) in [(&mut self.asks, &operation.asks), (&mut self.bids, &operation.bids)] {
+                let source = book.entry(operation.source).or_default();
+                if operation.snapshot {
+                    source.clear();
+                }
+                for (tick, size) in levels {
+                    match *size == 0.0 {
+                        true => source.remove(tick),
+                        false => source.insert(*tick, *size),
+                    };
+                }
+            }
+        }
+
+        fn side(&self, side: Side) -> MergedSide {
+            let book = match side {
+                Side::Ask => &self.asks,
+                Side::Bid => &self.bids,
+            };
+            let mut merged: BTreeMap<u32, BTreeMap<Source, f64>> = BTreeMap::new();
+            for (source, levels) in book {
+                for (tick, size) in levels {
+                    merged.entry(*tick).or_default().insert(*source, *size);
+                }
+            }
+            match side {
+                Side::Ask => merged.into_iter().collect(),
+                Side::Bid => merged.into_iter().rev().collect(),
+            }
+        }
+    }
+
+    fn book_side(orderbook: &Orderbook, side: Side) -> MergedSide {
+        orderbook.levels(side).iter()
+            .map(|x| ((x.price / TICK).round() as u32, x.source_size.iter().map(|(k, v)| (*k, *v)).collect()))
+            .collect()
+    }
+
+    fn levels(sizes: Vec<f64>) -> impl Strategy<Value=Vec<(u32, f64)>> {
+        vec((1u32..=30, select(sizes)), 0..8)
+    }
+
+    fn source() -> impl Strategy<Value=Source> {
+        select(vec![Source::Deribit, Source::OKX])
+    }
+
+    /// Snapshots and updates in any order, with deletes, duplicate prices and unsorted levels.
+    fn operations() -> impl Strategy<Value=Vec<Generated>> {
+        let operation = (source(), prop::bool::weighted(0.2), levels(vec![0.0, 0.5, 1.0, 2.5]), levels(vec![0.0, 0.5, 1.0, 2.5]))
+            .prop_map(|(source, snapshot, asks, bids)| Generated { source, snapshot, asks, bids });
+        vec(operation, 1..40)
+    }
+
+    /// A snapshot of each source, then updates that never delete.
+    fn operations_without_deletes() -> impl Strategy<Value=Vec<Generated>> {
+        let snapshot = |source| (levels(vec![0.5, 1.0, 2.5]), levels(vec![0.5, 1.0, 2.5]))
+            .prop_map(move |(asks, bids)| Generated { source, snapshot: true, asks, bids });
+        let update = (source(), levels(vec![0.5, 1.0, 2.5]), levels(vec![0.5, 1.0, 2.5]))
+            .prop_map(|(source, asks, bids)| Generated { source, snapshot: false, asks, bids });
+        (snapshot(Source::Deribit), snapshot(Source::OKX), vec(update, 0..30))
+            .prop_map(|(deribit, okx, updates)| [deribit, okx].into_iter().chain(updates).collect())
+    }
+
+    proptest! {
+        #[test]
+        fn should_match_the_reference_without_a_depth_cap(operations in operations()) {
+            // Given
+            let mut orderbook = Orderbook::new(0);
+            let mut reference = Reference::default();
+
+            // When
+            for operation in &operations {
+                orderbook.process(operation.operation());
+                reference.process(operation);
+            }
+
+            // Then
+            for side in [Side::Ask, Side::Bid] {
+                prop_assert_eq!(reference.side(side), book_side(&orderbook, side));
+            }
+        }
+
+        #[test]
+        fn should_keep_sorted_non_empty_levels_within_the_depth(operations in operations(), depth in 1usize..6) {
+            // Given
+            let mut orderbook = Orderbook::new(depth);
+
+            for operation in &operations {
+                // When
+                orderbook.process(operation.operation());
+
+                // Then
+                prop_assert!(orderbook.asks.len() <= depth * BUFFER_FACTOR);
+                prop_assert!(orderbook.bids.len() <= depth * BUFFER_FACTOR);
+                for (side, multiplier) in [(Side::Ask, 1.0), (Side::Bid, -1.0)] {
+                    let levels = orderbook.levels(side);
+                    prop_assert!(levels.len() <= depth);
+                    prop_assert!(levels.windows(2).all(|x| x[0].price * multiplier < x[1].price * multiplier));
+                    prop_assert!(levels.iter().all(|x| !x.source_size.is_empty()));
+                    prop_assert!(levels.iter().flat_map(|x| x.source_size.values()).all(|x| *x > EPSILON));
+                }
+            }
+        }
+
+        #[test]
+        fn should_show_the_reference_top_levels_while_nothing_is_deleted(
+            operations in operations_without_deletes(), depth in 1usize..6,
+        ) {
+            // Given
+            let mut orderbook = Orderbook::new(depth);
+            let mut reference = Reference::default();
+
+            // When
+            for operation in &operations {
+                orderbook.process(operation.operation());
+                reference.process(operation);
+            }
+
+            // Then
+            prop_assert!(orderbook.is_complete());
+            for side in [Side::Ask, Side::Bid] {
+                let expected: MergedSide = reference.side(side).into_iter().take(depth).collect();
+                prop_assert_eq!(expected, book_side(&orderbook, side));
+            }
+        }
+
+        #[test]
+        fn should_show_the_reference_top_levels_whenever_complete(operations in operations(), depth in 1usize..6) {
+            // Given
+            let mut orderbook = Orderbook::new(depth);
+            let mut reference = Reference::default();
+
+            for operation in &operations {
+                // When
+                orderbook.process(operation.operation());
+                reference.process(operation);
+
+                // Then
+                for side in [Side::Ask, Side::Bid] {
+                    if orderbook.incomplete_sources(side).is_empty() {
+                        let expected: MergedSide = reference.side(side).into_iter().take(depth).collect();
+                        prop_assert_eq!(expected, book_side(&orderbook, side));
+                    }
+                }
+            }
+        }
+
+        #[test]
+        fn should_replace_every_level_of_a_source_on_its_snapshot(
+            operations in operations(), asks in levels(vec![0.5, 1.0]), bids in levels(vec![0.5, 1.0]),
+        ) {
+            // Given
+            let mut orderbook = Orderbook::new(0);
+            for operation in &operations {
+                orderbook.process(operation.operation());
+            }
+            let snapshot = Generated { source: Source::OKX, snapshot: true, asks, bids };
+
+            // When
+            orderbook.process(snapshot.operation());
+
+            // Then
+            let mut reference = Reference::default();
+            reference.process(&snapshot);
+            for side in [Side::Ask, Side::Bid] {
+                let okx: MergedSide = book_side(&orderbook, side).into_iter()
+                    .filter_map(|(tick, mut sources)| {
+                        let size = sources.remove(&Source::OKX)?;
+                        Some((tick, BTreeMap::from([(Source::OKX, size)])))
+                    })
+                    .collect();
+                prop_assert_eq!(reference.side(side), okx);
+            }
+        }
+    }
 }
@@ -0,0 +1,204 @@
+use crate::orderbook::Orderbook;
+use bincode::Options;
+use std::{
+    fmt::{
+        Display,
+        Formatter,
+    },
+    fs,
+    io,
+    path::Path,
+};
+
+/// Leading bytes of a persisted book, the last one is the format version.
+const MAGIC: &[u8; 4] = b"TBK\x02";
+
+/// Largest book a file may decode into, a corrupt length can not make us allocate more.
+const MAX_DECODED_SIZE: u64 = 64 * 1024 * 1024;
+
+#[derive(Debug)]
+pub enum PersistenceError {
+    Decode(bincode::Error),
+    Encode(bincode::Error),
+    Io(io::Error),
+    UnknownFormat,
+}
+
+impl Display for PersistenceError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PersistenceError::Decode(err) => write!(f, "corrupt orderbook file: {}", err),
+            PersistenceError::Encode(err) => write!(f, "encoding failed: {}", err),
+            PersistenceError::Io(err) => write!(f, "orderbook file error: {}", err),
+            PersistenceError::UnknownFormat => f.write_str("not an orderbook file"),
+        }
+    }
+}
+
+/// Serializes the book, levels with their per-source sizes, statuses and last sequences included.
+pub fn encode(orderbook: &Orderbook) -> Result<Vec<u8>, PersistenceError> {
+    let mut buffer = MAGIC.to_vec();
+    bincode::serialize_into(&mut buffer, orderbook).map_err(PersistenceError::Encode)?;
+    Ok(buffer)
+}
+
+pub fn decode(data: &[u8]) -> Result<Orderbook, PersistenceError> {
+    decode_within(data, MAX_DECODED_SIZE)
+}
+
+fn decode_within(data: &[u8], limit: u64) -> Result<Orderbook, PersistenceError> {
+    let data = data.strip_prefix(MAGIC.as_slice()).ok_or(PersistenceError::UnknownFormat)?;
+    // The options `bincode::serialize` writes with, plus the size limit. Read as a stream since
+    // bincode only enforces the limit on readers.
+    bincode::options()
+        .with_fixint_encoding()
+        .allow_trailing_bytes()
+        .with_limit(limit)
+        .deserialize_from(data)
+        .map_err(PersistenceError::Decode)
+}
+
+/// Replaces the file at `path` with `data`, going through a temporary file so a crash never leaves
+/// a half written book behind.
+pub fn write(path: &Path, data: &[u8]) -> Result<(), PersistenceError> {
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".tmp");
+    fs::write(&temporary, data).map_err(PersistenceError::Io)?;
+    fs::rename(&temporary, path).map_err(PersistenceError::Io)
+}
+
+pub fn save(orderbook: &Orderbook, path: &Path) -> Result<(), PersistenceError> {
+    write(path, &encode(orderbook)?)
+}
+
+/// Loads a persisted book for a warm start, `None` when there is no file yet.
+///
+/// Every source in it is marked as resyncing, its levels stand in until the venue sends a fresh
+/// snapshot.
+pub fn load(path: &Path, depth: usize) -> Result<Option<Orderbook>, PersistenceError> {
+    let size = match fs::metadata(path) {
+        Ok(metadata) => metadata.len(),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(err) => return Err(PersistenceError::Io(err)),
+    };
+    if size > MAGIC.len() as u64 + MAX_DECODED_SIZE {
+        return Err(PersistenceError::Decode(Box::new(bincode::ErrorKind::SizeLimit)));
+    }
+    let data = fs::read(path).map_err(PersistenceError::Io)?;
+    let mut orderbook = decode(&data)?;
+    orderbook.set_depth(depth);
+    orderbook.mark_pending_resync();
+    Ok(Some(orderbook))
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        orderbook::{
+            Level,
+            Operation,
+            Orderbook,
+            Sequence,
+            Source,
+            VenueStatus,
+        },
+        persistence::{
+            decode,
+            decode_within,
+            encode,
+            load,
+            save,
+            PersistenceError,
+        },
+    };
+
+    #[test]
+    fn should_warm_start_from_a_saved_book() {
+        // Given
+        let path = std::env::temp_dir().join(format!("top-book-warm-{}.bin", std::process::id()));
+        let mut orderbook = Orderbook::new(5);
+        orderbook.process(Operation::Snapshot {
+            asks: vec![Level { price: 10.0, size: 1.0 }, Level { price: 11.0, size: 2.0 }],
+            bids: vec![Level { price: 9.0, size: 3.0 }],
+            source: Source::OKX,
+            sequence: Some(Sequence { id: 42, timestamp_ms: 1659142225882 }),
+        });
+        save(&orderbook, &path).unwrap();
+
+        // When
+        let loaded = load(&path, 1).unwrap().unwrap();
+
+        // Then
+        assert_eq!((1, 1), loaded.len());
+        assert_eq!(Some(10.0), loaded.best_ask());
+        assert_eq!(Some(9.0), loaded.best_bid());
+        assert_eq!(vec![(Source::OKX, VenueStatus::Resyncing)], loaded.statuses().collect::<Vec<_>>());
+        assert!(loaded.is_degraded());
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn should_start_cold_without_a_saved_book() {
+        // Given
+        let path = std::env::temp_dir().join("top-book-missing-book.bin");
+
+        // When
+        let resp = load(&path, 5);
+
+        // Then
+        assert!(matches!(resp, Ok(None)));
+    }
+
+    #[test]
+    fn should_reject_an_unknown_format() {
+        // Given
+        let data = b"{\"asks\":[]}";
+
+        // When
+        let resp = decode(data);
+
+        // Then
+        assert!(matches!(resp, Err(PersistenceError::UnknownFormat)));
+    }
+
+    #[test]
+    fn should_decode_what_it_encodes() {
+        // Given
+        let mut orderbook = Orderbook::new(5);
+        orderbook.process(Operation::Snapshot {
+            asks: vec![Level { price: 10.0, size: 1.0 }],
+            bids: vec![Level { price: 9.0, size: 3.0 }],
+            source: Source::Deribit,
+            sequence: None,
+        });
+
+        // When
+        let decoded = decode(&encode(&orderbook).unwrap()).unwrap();
+
+        // Then
+        assert_eq!(Some(10.0), decoded.best_ask());
+        assert_eq!(Some(9.0), decoded.best_bid());
+    }
+
+    #[test]
+    fn should_refuse_a_book_past_the_size_limit() {
+        // Given
+        let mut orderbook = Orderbook::new(5);
+        orderbook.process(Operation::Snapshot {
+            asks: vec![Level { price: 10.0, size: 1.0 }, Level { price: 11.0, size: 2.0 }],
+            bids: vec![],
+            source: Source::OKX,
+            sequence: None,
+        });
+        let data = encode(&orderbook).unwrap();
+
+        // When
+        let resp = decode_within(&data, 16);
+
+        // Then
+        match resp {
+            Err(PersistenceError::Decode(err)) => assert!(matches!(*err, bincode::ErrorKind::SizeLimit)),
+            _ => panic!("not a decode error"),
+        }
+    }
+}
@@ -0,0 +1,376 @@
+use crate::{
+    config::QueuePolicy,
+    metrics::{
+        metrics,
+        Metrics,
+    },
+    orderbook::{
+        Operation,
+        Source,
+    },
+};
+use std::{
+    collections::{
+        HashSet,
+        VecDeque,
+    },
+    sync::{
+        Arc,
+        Mutex,
+        MutexGuard,
+    },
+};
+use tokio::sync::{
+    Notify,
+    Semaphore,
+};
+
+#[derive(Debug)]
+pub struct Closed;
+
+struct State {
+    queue: VecDeque<Operation>,
+    senders: usize,
+    receiver_alive: bool,
+    /// Sources that lost an operation, their updates are dropped until a snapshot arrives.
+    desynced: HashSet<Source>,
+    /// Sources whose connector should subscribe again to get that snapshot.
+    resync_requested: HashSet<Source>,
+    high_water_mark: usize,
+}
+
+struct Shared {
+    state: Mutex<State>,
+    not_empty: Notify,
+    /// Free slots, only used by [`QueuePolicy::Block`].
+    slots: Semaphore,
+    capacity: usize,
+    policy: QueuePolicy,
+}
+
+impl Shared {
+    fn lock(&self) -> MutexGuard<'_, State> {
+        // Nothing panics while holding the lock, recover the state if something ever does.
+        self.state.lock().unwrap_or_else(|err| err.into_inner())
+    }
+}
+
+/// Bounded queue of operations between the connectors and the book.
+///
+/// What happens when the book falls behind depends on the [`QueuePolicy`]:
+/// - `Block` makes the connectors wait for a free slot.
+/// - `DropOldest` evicts the oldest operation, drops the rest of its source until that source
+///   resyncs, and asks its connector for a new snapshot.
+/// - `Conflate` merges every operation into the pending one of the same source, so the queue holds
+///   about one operation per source.
+///
+/// Venue statuses are neither dropped nor merged.
+///
+/// `Conflate` never waits nor drops, so `capacity` does not bound it: merging already keeps at most
+/// one book operation per source between two statuses, and the queue may hold more than `capacity`
+/// of them when there are more sources or statuses than slots.
+pub fn channel(capacity: usize, policy: QueuePolicy) -> (OperationSender, OperationReceiver) {
+    let shared = Arc::new(Shared {
+        state: Mutex::new(State {
+            queue: VecDeque::with_capacity(capacity),
+            senders: 1,
+            receiver_alive: true,
+            desynced: HashSet::new(),
+            resync_requested: HashSet::new(),
+            high_water_mark: 0,
+        }),
+        not_empty: Notify::new(),
+        slots: Semaphore::new(capacity),
+        capacity,
+        policy,
+    });
+    (OperationSender { shared: shared.clone() }, OperationReceiver { shared })
+}
+
+pub struct OperationSender {
+    shared: Arc<Shared>,
+}
+
+impl OperationSender {
+    pub async fn send(&self, operation: Operation) -> Result<(), Closed> {
+        if self.shared.policy == QueuePolicy::Block {
+            self.shared.slots.acquire().await.map_err(|_| Closed)?.forget();
+        }
+
+        let mut state = self.shared.lock();
+        if !state.receiver_alive {
+            return Err(Closed);
+        }
+        match self.shared.policy {
+            QueuePolicy::Block => state.queue.push_back(operation),
+            QueuePolicy::DropOldest => self.push_dropping_oldest(&mut state, operation),
+            QueuePolicy::Conflate => OperationSender::push_conflating(&mut state, operation),
+        }
+
+        let len = state.queue.len();
+        metrics().queue_depth.set(len as i64);
+        if len > state.high_water_mark {
+            state.high_water_mark = len;
+            metrics().queue_high_water_mark.set(len as i64);
+        }
+        drop(state);
+        self.shared.not_empty.notify_one();
+        Ok(())
+    }
+
+    fn push_dropping_oldest(&self, state: &mut State, operation: Operation) {
+        // Statuses and own orders are tiny and the book needs every one of them, they are never
+        // dropped.
+        let is_status = |x: &Operation| matches!(x, Operation::Status { .. } | Operation::Orders { .. });
+        if state.queue.len() >= self.shared.capacity {
+            if let Some(position) = state.queue.iter().position(|x| !is_status(x)) {
+                let source = state.queue.remove(position).expect("position is in the queue").source();
+                let before = state.queue.len();
+                state.queue.retain(|x| x.source() != source || is_status(x));
+                metrics().queue_dropped.with_label_values(&[Metrics::source_label(source)])
+                    .inc_by((1 + before - state.queue.len()) as u64);
+                state.desynced.insert(source);
+                state.resync_requested.insert(source);
+            }
+        }
+
+        let source = operation.source();
+        match operation {
+            Operation::Snapshot { .. } => {
+                state.desynced.remove(&source);
+            }
+            Operation::Update { .. } if state.desynced.contains(&source) => {
+                metrics().queue_dropped.with_label_values(&[Metrics::source_label(source)]).inc();
+                return;
+            }
+            _ => {}
+        }
+        state.queue.push_back(operation);
+    }
+
+    fn push_conflating(state: &mut State, operation: Operation) {
+        let source = operation.source();
+        let operation = match state.queue.iter_mut().rev().find(|x| x.source() == source) {
+            Some(pending) => pending.conflate(operation),
+            None => Some(operation),
+        };
+        match operation {
+            Some(operation) => state.queue.push_back(operation),
+            None => metrics().queue_conflated.with_label_values(&[Metrics::source_label(source)]).inc(),
+        }
+    }
+
+    /// Whether the queue dropped operations from `source`, the request is cleared once taken.
+    pub fn take_resync(&self, source: Source) -> bool {
+        self.shared.lock().resync_requested.remove(&source)
+    }
+}
+
+impl Clone for OperationSender {
+    fn clone(&self) -> Self {
+        self.shared.lock().senders += 1;
+        Self { shared: self.shared.clone() }
+    }
+}
+
+impl Drop for OperationSender {
+    fn drop(&mut self) {
+        let mut state = self.shared.lock();
+        state.senders -= 1;
+        if state.senders == 0 {
+            drop(state);
+            self.shared.not_empty.notify_one();
+        }
+    }
+}
+
+pub struct OperationReceiver {
+    shared: Arc<Shared>,
+}
+
+impl OperationReceiver {
+    /// Waits for the next operation, `None` once every sender is gone and the queue is drained.
+    pub async fn recv(&mut self) -> Option<Operation> {
+        loop {
+            let notified = self.shared.not_empty.notified();
+            {
+                let mut state = self.shared.lock();
+                if let Some(operation) = state.queue.pop_front() {
+                    metrics().queue_depth.set(state.queue.len() as i64);
+                    drop(state);
+                    if self.shared.policy == QueuePolicy::Block {
+                        self.shared.slots.add_permits(1);
+                    }
+                    return Some(operation);
+                }
+                if state.senders == 0 {
+                    return None;
+                }
+            }
+            notified.await;
+        }
+    }
+
+    /// Asks the connector of `source` for a new snapshot, as if the queue dropped its operations.
+    pub fn request_resync(&self, source: Source) {
+        self.shared.lock().resync_requested.insert(source);
+    }
+}
+
+impl Drop for OperationReceiver {
+    fn drop(&mut self) {
+        self.shared.lock().receiver_alive = false;
+        self.shared.slots.close();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        config::QueuePolicy,
+        orderbook::{
+            Level,
+            Operation,
+            Source,
+            VenueStatus,
+        },
+        queue::channel,
+    };
+    use std::time::Duration;
+
+    fn update(source: Source, price: f64) -> Operation {
+        Operation::Update {
+            asks: vec![Level { price, size: 1.0 }],
+            bids: vec![],
+            source,
+            sequence: None,
+        }
+    }
+
+    fn snapshot(source: Source, price: f64) -> Operation {
+        Operation::Snapshot {
+            asks: vec![Level { price, size: 1.0 }],
+            bids: vec![],
+            source,
+            sequence: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn should_block_until_there_is_a_free_slot() {
+        // Given
+        let (sender, mut receiver) = channel(1, QueuePolicy::Block);
+        sender.send(update(Source::OKX, 1.0)).await.unwrap();
+
+        // When
+        let blocked = tokio::time::timeout(Duration::from_millis(20), sender.send(update(Source::OKX, 2.0))).await;
+        receiver.recv().await.unwrap();
+        let unblocked = tokio::time::timeout(Duration::from_millis(20), sender.send(update(Source::OKX, 2.0))).await;
+
+        // Then
+        assert!(blocked.is_err());
+        assert!(unblocked.is_ok());
+    }
+
+    #[tokio::test]
+    async fn should_drop_the_oldest_and_request_a_resync() {
+        // Given
+        let (sender, mut receiver) = channel(3, QueuePolicy::DropOldest);
+        sender.send(update(Source::OKX, 1.0)).await.unwrap();
+        sender.send(update(Source::Deribit, 2.0)).await.unwrap();
+        sender.send(update(Source::Deribit, 3.0)).await.unwrap();
+
+        // When
+        sender.send(update(Source::OKX, 4.0)).await.unwrap();
+        let resync = sender.take_resync(Source::OKX);
+        sender.send(snapshot(Source::OKX, 5.0)).await.unwrap();
+        drop(sender);
+
+        // Then
+        assert!(resync);
+        let mut received = vec![];
+        while let Some(operation) = receiver.recv().await {
+            received.push((operation.source(), operation.kind()));
+        }
+        assert_eq!(vec![
+            (Source::Deribit, "update"),
+            (Source::Deribit, "update"),
+            (Source::OKX, "snapshot"),
+        ], received);
+    }
+
+    #[tokio::test]
+    async fn should_conflate_per_source() {
+        // Given
+        let (sender, mut receiver) = channel(8, QueuePolicy::Conflate);
+
+        // When
+        sender.send(update(Source::OKX, 1.0)).await.unwrap();
+        sender.send(update(Source::Deribit, 2.0)).await.unwrap();
+        sender.send(update(Source::OKX, 3.0)).await.unwrap();
+        drop(sender);
+
+        // Then
+        let okx = receiver.recv().await.unwrap();
+        assert_eq!(Source::OKX, okx.source());
+        assert_eq!((2, 0), okx.len());
+        assert_eq!(Source::Deribit, receiver.recv().await.unwrap().source());
+        assert!(receiver.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn should_conflate_past_the_capacity_without_blocking() {
+        // Given
+        let (sender, mut receiver) = channel(1, QueuePolicy::Conflate);
+
+        // When
+        let sent = tokio::time::timeout(Duration::from_millis(20), async {
+            for price in 1..=10 {
+                sender.send(update(Source::OKX, price as f64)).await.unwrap();
+                sender.send(update(Source::Deribit, price as f64)).await.unwrap();
+            }
+        }).await;
+        drop(sender);
+
+        // Then
+        assert!(sent.is_ok());
+        let mut received = vec![];
+        while let Some(operation) = receiver.recv().await {
+            received.push((operation.source(), operation.len()));
+        }
+        assert_eq!(vec![(Source::OKX, (10, 0)), (Source::Deribit, (10, 0))], received);
+    }
+
+    #[tokio::test]
+    async fn should_fail_to_send_once_the_receiver_is_gone() {
+        // Given
+        let (sender, receiver) = channel(1, QueuePolicy::Block);
+        sender.send(update(Source::OKX, 1.0)).await.unwrap();
+
+        // When
+        drop(receiver);
+
+        // Then
+        assert!(sender.send(update(Source::OKX, 2.0)).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn should_keep_statuses_apart() {
+        // Given
+        let (sender, mut receiver) = channel(1, QueuePolicy::Conflate);
+        sender.send(update(Source::OKX, 1.0)).await.unwrap();
+
+        // When
+        sender.send(Operation::Status { status: VenueStatus::Down, source: Source::OKX }).await.unwrap();
+        sender.send(snapshot(Source::OKX, 2.0)).await.unwrap();
+        sender.send(update(Source::OKX, 3.0)).await.unwrap();
+        drop(sender);
+
+        // Then
+        let mut received = vec![];
+        while let Some(operation) = receiver.recv().await {
+            received.push(operation.kind());
+        }
+        assert_eq!(vec!["update", "status", "snapshot"], received);
+    }
+}
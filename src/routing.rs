@@ -0,0 +1,394 @@
+use crate::{
+    config::FeesConfig,
+    fees::{
+        effective_price,
+        is_better,
+        Liquidity,
+    },
+    orderbook::{
+        Orderbook,
+        Side,
+        Source,
+        EPSILON,
+    },
+};
+use std::collections::{
+    BTreeMap,
+    BTreeSet,
+};
+
+/// What an account can do on one venue.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct VenueLimits {
+    /// Smallest child order the venue accepts.
+    pub min_size: f64,
+    /// Child orders are a multiple of this, any size when zero.
+    pub lot_size: f64,
+    /// What can be spent: quote currency, fees included, when buying and base currency when
+    /// selling. Unlimited when not set.
+    pub balance: Option<f64>,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum RoutingError {
+    InvalidQuantity(f64),
+    InvalidLimits(Source),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChildOrder {
+    pub source: Source,
+    pub quantity: f64,
+    /// Worst quoted price the child reaches, its limit price.
+    pub limit_price: f64,
+    pub average_price: f64,
+    pub fee: f64,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Plan {
+    /// One child per venue, in [`Source`] order.
+    pub children: Vec<ChildOrder>,
+    pub quantity: f64,
+    /// What the plan pays when buying, or receives when selling, fees included.
+    pub net_notional: f64,
+    pub unfilled: f64,
+}
+
+/// Part of a level quoted by one venue.
+struct Slice {
+    source: Source,
+    price: f64,
+    effective_price: f64,
+    size: f64,
+}
+
+struct Planner<'a> {
+    side: Side,
+    fees: &'a FeesConfig,
+    limits: &'a BTreeMap<Source, VenueLimits>,
+    /// Every venue's quotes, best effective price first.
+    slices: Vec<Slice>,
+}
+
+impl<'a> Planner<'a> {
+    fn limits(&self, source: Source) -> VenueLimits {
+        self.limits.get(&source).copied().unwrap_or_default()
+    }
+
+    /// Balance used by trading `quantity` at `effective_price`.
+    fn usage(&self, quantity: f64, effective_price: f64) -> f64 {
+        match self.side {
+            Side::Ask => quantity * effective_price,
+            Side::Bid => quantity,
+        }
+    }
+
+    /// Walks the quotes of `source`, skipping the first `from` of them, for `quantity`.
+    ///
+    /// Returns the notional, the balance used and the worst price, `None` when the venue does not
+    /// quote that much.
+    fn walk(&self, source: Source, from: f64, quantity: f64) -> Option<(f64, f64, f64)> {
+        let (mut skip, mut left) = (from, quantity);
+        let (mut notional, mut usage, mut worst) = (0.0, 0.0, 0.0);
+        for slice in self.slices.iter().filter(|x| x.source == source) {
+            if left <= EPSILON {
+                break;
+            }
+            let skipped = slice.size.min(skip);
+            skip -= skipped;
+            let taken = (slice.size - skipped).min(left);
+            if taken <= EPSILON {
+                continue;
+            }
+            left -= taken;
+            notional += taken * slice.price;
+            usage += self.usage(taken, slice.effective_price);
+            worst = slice.price;
+        }
+        if left > EPSILON {
+            return None;
+        }
+        Some((notional, usage, worst))
+    }
+
+    /// Fills from the best effective quotes first, leaving out `excluded` venues.
+    fn greedy(&self, quantity: f64, excluded: &BTreeSet<Source>) -> BTreeMap<Source, f64> {
+        let mut resp = BTreeMap::new();
+        let mut used: BTreeMap<Source, f64> = BTreeMap::new();
+        let mut left = quantity;
+        for slice in self.slices.iter().filter(|x| !excluded.contains(&x.source)) {
+            if left <= EPSILON {
+                break;
+            }
+            let mut taken = slice.size.min(left);
+            if let Some(balance) = self.limits(slice.source).balance {
+                let available = balance - used.get(&slice.source).copied().unwrap_or_default();
+                taken = taken.min(available / self.usage(1.0, slice.effective_price)).max(0.0);
+            }
+            if taken <= EPSILON {
+                continue;
+            }
+            left -= taken;
+            *resp.entry(slice.source).or_default() += taken;
+            *used.entry(slice.source).or_default() += self.usage(taken, slice.effective_price);
+        }
+        resp
+    }
+
+    fn round_to_lot(quantity: f64, lot_size: f64) -> f64 {
+        if lot_size > 0.0 {
+            (quantity / lot_size + EPSILON).floor() * lot_size
+        } else {
+            quantity
+        }
+    }
+
+    /// Adds whole lots, best effective price first, to get back what rounding to lots lost.
+    fn top_up(&self, allocation: &mut BTreeMap<Source, f64>, mut left: f64) {
+        let sources: BTreeSet<Source> = self.slices.iter().map(|x| x.source).collect();
+        while left > EPSILON {
+            let mut best: Option<(Source, f64, f64)> = None;
+            for source in &sources {
+                let limits = self.limits(*source);
+                let current = allocation.get(source).copied().unwrap_or_default();
+                let mut chunk = if limits.lot_size > 0.0 { limits.lot_size } else { left };
+                if current <= EPSILON && chunk < limits.min_size {
+                    chunk = match limits.lot_size > 0.0 {
+                        true => (limits.min_size / limits.lot_size - EPSILON).ceil() * limits.lot_size,
+                        false => limits.min_size,
+                    };
+                }
+                if chunk > left + EPSILON {
+                    continue;
+                }
+                let (notional, usage, _) = match self.walk(*source, current, chunk) {
+                    Some(x) => x,
+                    None => continue,
+                };
+                if let Some(balance) = limits.balance {
+                    let (_, used, _) = self.walk(*source, 0.0, current).unwrap_or_default();
+                    if used + usage > balance + EPSILON {
+                        continue;
+                    }
+                }
+                let rate = self.fees.schedule(*source).rate(Liquidity::Taker);
+                let price = effective_price(self.side, notional / chunk, rate);
+                if best.map(|(_, _, x)| is_better(self.side, price, x)).unwrap_or(true) {
+                    best = Some((*source, chunk, price));
+                }
+            }
+            match best {
+                Some((source, chunk, _)) => {
+                    *allocation.entry(source).or_default() += chunk;
+                    left -= chunk;
+                }
+                None => break,
+            }
+        }
+    }
+}
+
+/// Splits a parent order taking `quantity` from `side` of the book into one child order per venue,
+/// greedily by effective price after taker fees.
+///
+/// Quotes are taken best effective price first, then every child is rounded down to its venue's
+/// lot size, venues that end up under their minimum size are left out and whole lots make up for
+/// the rounding. Without venue limits this is the lowest total cost, with them it is a heuristic
+/// that can miss a cheaper mix of lots. The plan only depends on its inputs, ties go to the first
+/// [`Source`].
+///
+/// Plan on [`Orderbook::external`] to leave our own resting orders out of it.
+pub fn plan(
+    orderbook: &Orderbook, side: Side, quantity: f64, fees: &FeesConfig, limits: &BTreeMap<Source, VenueLimits>,
+) -> Result<Plan, RoutingError> {
+    if !quantity.is_finite() || quantity <= 0.0 {
+        return Err(RoutingError::InvalidQuantity(quantity));
+    }
+    for (source, x) in limits {
+        let valid = [x.min_size, x.lot_size, x.balance.unwrap_or_default()].iter().all(|x| x.is_finite() && *x >= 0.0);
+        if !valid {
+            return Err(RoutingError::InvalidLimits(*source));
+        }
+    }
+
+    let mut slices: Vec<Slice> = orderbook.levels(side).iter()
+        .flat_map(|level| level.source_size.iter().map(move |(source, size)| (level.price, *source, *size)))
+        .map(|(price, source, size)| Slice {
+            source,
+            price,
+            effective_price: effective_price(side, price, fees.schedule(source).rate(Liquidity::Taker)),
+            size,
+        })
+        .collect();
+    slices.sort_by(|a, b| {
+        let by_price = match side {
+            Side::Ask => a.effective_price.total_cmp(&b.effective_price),
+            Side::Bid => b.effective_price.total_cmp(&a.effective_price),
+        };
+        by_price.then(a.source.cmp(&b.source))
+    });
+    let planner = Planner { side, fees, limits, slices };
+
+    let mut excluded = BTreeSet::new();
+    let mut allocation = loop {
+        let mut allocation = planner.greedy(quantity, &excluded);
+        for (source, x) in allocation.iter_mut() {
+            *x = Planner::round_to_lot(*x, planner.limits(*source).lot_size);
+        }
+        let too_small: Vec<Source> = allocation.iter()
+            .filter(|(source, x)| **x > EPSILON && **x < planner.limits(**source).min_size - EPSILON)
+            .map(|(source, _)| *source)
+            .collect();
+        if too_small.is_empty() {
+            break allocation;
+        }
+        excluded.extend(too_small);
+    };
+    allocation.retain(|_, x| *x > EPSILON);
+    let allocated: f64 = allocation.values().sum();
+    planner.top_up(&mut allocation, quantity - allocated);
+
+    let mut resp = Plan {
+        children: vec![],
+        quantity: 0.0,
+        net_notional: 0.0,
+        unfilled: 0.0,
+    };
+    for (source, quantity) in allocation {
+        let (notional, _, worst) = planner.walk(source, 0.0, quantity).expect("allocations fit in the quotes");
+        let fee = notional * fees.schedule(source).rate(Liquidity::Taker);
+        resp.quantity += quantity;
+        resp.net_notional += match side {
+            Side::Ask => notional + fee,
+            Side::Bid => notional - fee,
+        };
+        resp.children.push(ChildOrder {
+            source,
+            quantity,
+            limit_price: worst,
+            average_price: notional / quantity,
+            fee,
+        });
+    }
+    resp.unfilled = (quantity - resp.quantity).max(0.0);
+    Ok(resp)
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        config::{
+            FeeSchedule,
+            FeesConfig,
+        },
+        orderbook::{
+            Level,
+            Operation,
+            Orderbook,
+            Side,
+            Source,
+        },
+        routing::{
+            plan,
+            RoutingError,
+            VenueLimits,
+        },
+    };
+    use std::collections::BTreeMap;
+
+    fn fees() -> FeesConfig {
+        FeesConfig {
+            deribit: FeeSchedule { maker_bps: 0.0, taker_bps: 10.0 },
+            okx: FeeSchedule { maker_bps: 0.0, taker_bps: 0.0 },
+        }
+    }
+
+    fn orderbook() -> Orderbook {
+        let mut orderbook = Orderbook::new(20);
+        orderbook.process(Operation::Snapshot {
+            asks: vec![Level { price: 100.0, size: 2.0 }, Level { price: 100.05, size: 5.0 }],
+            bids: vec![Level { price: 99.0, size: 1.0 }],
+            source: Source::Deribit,
+            sequence: None,
+        });
+        orderbook.process(Operation::Snapshot {
+            asks: vec![Level { price: 100.05, size: 1.0 }, Level { price: 100.2, size: 5.0 }],
+            bids: vec![Level { price: 98.0, size: 1.0 }],
+            source: Source::OKX,
+            sequence: None,
+        });
+        orderbook
+    }
+
+    fn quantities(plan: &crate::routing::Plan) -> Vec<(Source, f64)> {
+        plan.children.iter().map(|x| (x.source, (x.quantity * 1e6).round() / 1e6)).collect()
+    }
+
+    #[test]
+    fn should_take_the_cheapest_quotes_after_fees() {
+        // Given
+        let orderbook = orderbook();
+
+        // When
+        let plan = plan(&orderbook, Side::Ask, 4.0, &fees(), &BTreeMap::new()).unwrap();
+
+        // Then
+        // OKX at 100.05 is the best after fees, then Deribit at 100 and 100.05, which cost 100.1 and
+        // about 100.15 with its fee, still less than OKX at 100.2.
+        assert_eq!(vec![(Source::Deribit, 3.0), (Source::OKX, 1.0)], quantities(&plan));
+        assert_eq!(100.05, plan.children[1].limit_price);
+        assert_eq!(0.0, plan.unfilled);
+        let paid = 2.0 * 100.0 * 1.001 + 100.05 * 1.001 + 100.05;
+        assert!((plan.net_notional - paid).abs() < 1e-9);
+    }
+
+    #[test]
+    fn should_round_to_lots_and_leave_out_venues_under_their_minimum() {
+        // Given
+        let orderbook = orderbook();
+        let limits = BTreeMap::from([
+            (Source::Deribit, VenueLimits { min_size: 0.0, lot_size: 1.0, balance: None }),
+            (Source::OKX, VenueLimits { min_size: 2.0, lot_size: 0.1, balance: None }),
+        ]);
+
+        // When
+        let plan = plan(&orderbook, Side::Ask, 2.5, &fees(), &limits).unwrap();
+
+        // Then
+        // OKX would get 1, under its minimum, so Deribit takes 2 and OKX cannot take the last 0.5.
+        assert_eq!(vec![(Source::Deribit, 2.0)], quantities(&plan));
+        assert!((plan.unfilled - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn should_keep_within_the_balance() {
+        // Given
+        let orderbook = orderbook();
+        let limits = BTreeMap::from([
+            (Source::OKX, VenueLimits { min_size: 0.0, lot_size: 0.0, balance: Some(0.5) }),
+        ]);
+
+        // When
+        let plan = plan(&orderbook, Side::Bid, 2.0, &fees(), &limits).unwrap();
+
+        // Then
+        assert_eq!(vec![(Source::Deribit, 1.0), (Source::OKX, 0.5)], quantities(&plan));
+        assert!((plan.unfilled - 0.5).abs() < 1e-9);
+    }
+
+    #[test]
+    fn should_reject_invalid_limits() {
+        // Given
+        let orderbook = orderbook();
+        let limits = BTreeMap::from([
+            (Source::OKX, VenueLimits { min_size: -1.0, lot_size: 0.0, balance: None }),
+        ]);
+
+        // When
+        let resp = plan(&orderbook, Side::Bid, 1.0, &fees(), &limits);
+
+        // Then
+        assert_eq!(Err(RoutingError::InvalidLimits(Source::OKX)), resp);
+    }
+}
@@ -0,0 +1,232 @@
+use crate::{
+    config::{
+        RestartPolicy,
+        SupervisorConfig,
+    },
+    connector::{
+        log,
+        ConnectorError,
+        ErrorPolicy,
+        Shutdown,
+    },
+    metrics::{
+        metrics,
+        Metrics,
+    },
+    orderbook::{
+        Operation,
+        Source,
+        VenueStatus,
+    },
+    queue::OperationSender,
+};
+use std::{
+    fmt::Debug,
+    future::Future,
+    time::Duration,
+};
+use tokio::{
+    task::JoinError,
+    time::Instant,
+};
+
+#[derive(Debug)]
+pub enum SupervisorError<E> {
+    Failed(E),
+    Panicked(JoinError),
+}
+
+/// Runs the connector built by `start` as its own task, restarting it as `config` says.
+///
+/// Every time the connector stops on an error, or panics, the venue is reported down to the book,
+/// which carries on with the other venues. Returns once the connector stops cleanly, on shutdown,
+/// on an error whose policy is [`ErrorPolicy::Stop`], since a restart would fail the same way, or
+/// with the last failure once the supervisor gives up.
+pub async fn supervise<F, Fut, E>(
+    source: Source, config: SupervisorConfig, sender: OperationSender, mut shutdown: Shutdown,
+    mut start: F,
+) -> Result<(), SupervisorError<E>>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output=Result<(), E>> + Send + 'static,
+        E: ConnectorError + Debug + Send + 'static,
+{
+    let initial_backoff = Duration::from_millis(config.backoff_initial_ms);
+    let max_backoff = Duration::from_millis(config.backoff_max_ms);
+    let mut backoff = initial_backoff;
+    let mut restarts = 0;
+
+    loop {
+        let started = Instant::now();
+        let err = match tokio::spawn(start()).await {
+            Ok(Ok(())) => return Ok(()),
+            Ok(Err(err)) => SupervisorError::Failed(err),
+            Err(err) => SupervisorError::Panicked(err),
+        };
+        let status = Operation::Status { status: VenueStatus::Down, source };
+        if sender.send(status).await.is_err() || shutdown.is_triggered() {
+            return Err(err);
+        }
+
+        if started.elapsed() >= max_backoff {
+            backoff = initial_backoff;
+            restarts = 0;
+        }
+        let exhausted = config.max_restarts.map(|x| restarts >= x).unwrap_or(false);
+        let fatal = matches!(&err, SupervisorError::Failed(err) if err.policy() == ErrorPolicy::Stop);
+        if config.restart == RestartPolicy::Never || exhausted || fatal {
+            log(format!("{:?} connector stopped for good after {:?}\n", source, err)).await;
+            return Err(err);
+        }
+
+        log(format!("{:?} connector stopped after {:?}, restarting in {:?}\n", source, err, backoff)).await;
+        tokio::select! {
+            _ = tokio::time::sleep(backoff) => {}
+            _ = shutdown.wait() => return Err(err),
+        }
+        restarts += 1;
+        backoff = (backoff * 2).min(max_backoff);
+        metrics().restarts.with_label_values(&[Metrics::source_label(source)]).inc();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        config::{
+            QueuePolicy,
+            RestartPolicy,
+            SupervisorConfig,
+        },
+        connector::{
+            shutdown,
+            ConnectorError,
+            ErrorPolicy,
+        },
+        orderbook::{
+            Operation,
+            Source,
+            VenueStatus,
+        },
+        queue,
+        supervisor::{
+            supervise,
+            SupervisorError,
+        },
+    };
+    use std::sync::{
+        atomic::{
+            AtomicU32,
+            Ordering,
+        },
+        Arc,
+    };
+
+    #[derive(Debug, PartialEq)]
+    struct TestError(ErrorPolicy);
+
+    impl ConnectorError for TestError {
+        fn policy(&self) -> ErrorPolicy {
+            self.0
+        }
+    }
+
+    fn config(restart: RestartPolicy, max_restarts: Option<u32>) -> SupervisorConfig {
+        SupervisorConfig {
+            restart,
+            max_restarts,
+            backoff_initial_ms: 1,
+            backoff_max_ms: 1_000,
+        }
+    }
+
+    #[tokio::test]
+    async fn should_restart_until_the_limit_and_report_the_venue_down() {
+        // Given
+        let (sender, mut receiver) = queue::channel(16, QueuePolicy::Block);
+        let runs = Arc::new(AtomicU32::new(0));
+        let counter = runs.clone();
+
+        // When
+        let resp = supervise(
+            Source::OKX, config(RestartPolicy::OnFailure, Some(2)), sender, shutdown().1,
+            move || {
+                let counter = counter.clone();
+                async move {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    Err::<(), _>(TestError(ErrorPolicy::Reconnect))
+                }
+            },
+        ).await;
+
+        // Then
+        assert!(matches!(resp, Err(SupervisorError::Failed(TestError(ErrorPolicy::Reconnect)))));
+        assert_eq!(3, runs.load(Ordering::SeqCst));
+        for _ in 0..3 {
+            match receiver.recv().await {
+                Some(Operation::Status { status, source }) => {
+                    assert_eq!(VenueStatus::Down, status);
+                    assert_eq!(Source::OKX, source);
+                }
+                _ => panic!("not a status"),
+            }
+        }
+        assert!(receiver.recv().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn should_isolate_a_panicking_connector() {
+        // Given
+        let (sender, _receiver) = queue::channel(16, QueuePolicy::Block);
+
+        // When
+        let resp = supervise(
+            Source::Deribit, config(RestartPolicy::Never, None), sender, shutdown().1,
+            || async { panic!("connector bug") as Result<(), TestError> },
+        ).await;
+
+        // Then
+        assert!(matches!(resp, Err(SupervisorError::Panicked(_))));
+    }
+
+    #[tokio::test]
+    async fn should_return_when_the_connector_stops_cleanly() {
+        // Given
+        let (sender, _receiver) = queue::channel(16, QueuePolicy::Block);
+
+        // When
+        let resp = supervise(
+            Source::OKX, config(RestartPolicy::OnFailure, None), sender, shutdown().1,
+            || async { Ok::<(), TestError>(()) },
+        ).await;
+
+        // Then
+        assert!(resp.is_ok());
+    }
+
+    #[tokio::test]
+    async fn should_not_restart_a_connector_that_must_stop() {
+        // Given
+        let (sender, mut receiver) = queue::channel(16, QueuePolicy::Block);
+        let runs = Arc::new(AtomicU32::new(0));
+        let counter = runs.clone();
+
+        // When
+        let resp = supervise(
+            Source::Deribit, config(RestartPolicy::OnFailure, None), sender, shutdown().1,
+            move || {
+                let counter = counter.clone();
+                async move {
+                    counter.fetch_add(1, Ordering::SeqCst);
+                    Err::<(), _>(TestError(ErrorPolicy::Stop))
+                }
+            },
+        ).await;
+
+        // Then
+        assert!(matches!(resp, Err(SupervisorError::Failed(TestError(ErrorPolicy::Stop)))));
+        assert_eq!(1, runs.load(Ordering::SeqCst));
+        assert!(matches!(receiver.recv().await, Some(Operation::Status { status: VenueStatus::Down, .. })));
+        assert!(receiver.recv().await.is_none());
+    }
+}